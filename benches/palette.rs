@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{Rgba, RgbaImage};
+use image2nord::colors::{apply_palette_filter, DistanceMetric, Dither, Palette, PaletteColors};
+
+/// a diagonal gradient with grain on top, so the pixel cache sees about as many distinct colors as in a photo
+fn photo_like(width: u32, height: u32) -> RgbaImage {
+    let mut seed = 0x9e37_79b9u32;
+    RgbaImage::from_fn(width, height, |x, y| {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        let grain = (seed >> 28) as u8;
+        let r = (x * 255 / width) as u8;
+        let g = (y * 255 / height) as u8;
+        let b = ((x + y) * 255 / (width + height)) as u8;
+        Rgba([r.saturating_add(grain), g.saturating_add(grain), b.saturating_add(grain), 255])
+    })
+}
+
+fn apply_palette_filter_benchmark(c: &mut Criterion) {
+    let colors = PaletteColors::from_palette(Palette::Nord);
+    let mut group = c.benchmark_group("apply_palette_filter");
+    // a 12 MP conversion takes long enough that the default 100 samples would run for minutes
+    group.sample_size(10);
+    for (width, height) in [(512, 512), (4000, 3000)] {
+        let image = photo_like(width, height);
+        group.bench_with_input(BenchmarkId::from_parameter(format!("{width}x{height}")), &image, |b, image| {
+            b.iter_batched_ref(
+                || image.clone(),
+                |image| apply_palette_filter(image, &colors, DistanceMetric::Brightness, Dither::None, 0.85, false, false),
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, apply_palette_filter_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,201 @@
+use std::{io::Cursor, time::Duration};
+
+use anyhow::{bail, Result};
+use image::{
+    codecs::{gif::{GifDecoder, GifEncoder, Repeat}, webp::WebPDecoder},
+    imageops::{self, FilterType}, AnimationDecoder, Delay, DynamicImage, Frame,
+};
+
+/// pixels of all frames together, every frame is decoded at the size of the whole animation
+pub const MAX_TOTAL_MEGAPIXELS: usize = 200;
+
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub repeat: Repeat,
+    /// frames of the file, frames skipped while decoding are counted too
+    pub source_frames: usize,
+}
+
+impl Animation {
+    /// longer animations than max_frames are rejected, or thinned out while decoding with skip_frames
+    pub fn decode_gif(bytes: &[u8], max_frames: usize, skip_frames: bool) -> Result<Self> {
+        let decoder = GifDecoder::new(Cursor::new(bytes))?;
+        let (frames, source_frames) = collect_frames(decoder, max_frames, skip_frames)?;
+        Ok(Animation { frames, repeat: read_repeat(bytes), source_frames })
+    }
+
+    /// static WebPs come back with a single frame, the loop count isn't exposed so animations loop forever
+    pub fn decode_webp(bytes: &[u8], max_frames: usize, skip_frames: bool) -> Result<Self> {
+        let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+        if !decoder.has_animation() {
+            let frame = Frame::new(DynamicImage::from_decoder(decoder)?.to_rgba8());
+            return Ok(Animation { frames: vec![frame], repeat: Repeat::Infinite, source_frames: 1 });
+        }
+        let (frames, source_frames) = collect_frames(decoder, max_frames, skip_frames)?;
+        Ok(Animation { frames, repeat: Repeat::Infinite, source_frames })
+    }
+
+    /// applies `f` to every frame while keeping position and delay of the frame,
+    /// `progress` gets the number of done frames and the total every few frames
+    pub fn map_frames(self, f: impl Fn(DynamicImage) -> DynamicImage, progress: impl Fn(usize, usize)) -> Self {
+        const PROGRESS_INTERVAL: usize = 10;
+        let total = self.frames.len();
+        let frames = self.frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                if i % PROGRESS_INTERVAL == 0 {
+                    progress(i, total);
+                }
+                let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+                let image = f(DynamicImage::ImageRgba8(frame.into_buffer())).to_rgba8();
+                Frame::from_parts(image, left, top, delay)
+            })
+            .collect();
+        Animation { frames, ..self }
+    }
+
+    /// keeps every nth frame so at most max_frames are left,
+    /// the kept frames also last as long as the dropped ones after them
+    pub fn skip_frames(self, max_frames: usize) -> Self {
+        let step = self.frames.len().div_ceil(max_frames.max(1));
+        if step <= 1 {
+            return self;
+        }
+        Animation { frames: merge_frames(self.frames, step), ..self }
+    }
+
+    /// scales all frames by the same factor so the animation fits into max_width x max_height
+    pub fn fit_within(self, max_width: u32, max_height: u32) -> Self {
+        let (width, height) = self.dimensions();
+        if width <= max_width && height <= max_height {
+            return self;
+        }
+        let scale = (max_width as f32 / width as f32).min(max_height as f32 / height as f32);
+        let scaled = |value: u32| ((value as f32 * scale).round() as u32).max(1);
+        let frames = self.frames
+            .into_iter()
+            .map(|frame| {
+                let (left, top, delay) = (frame.left(), frame.top(), frame.delay());
+                let buffer = frame.into_buffer();
+                let resized = imageops::resize(&buffer, scaled(buffer.width()), scaled(buffer.height()), FilterType::Triangle);
+                Frame::from_parts(resized, scaled(left), scaled(top), delay)
+            })
+            .collect();
+        Animation { frames, ..self }
+    }
+
+    /// size of the first frame, the decoders hand out every frame at the size of the whole animation
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames.first().map_or((0, 0), |frame| frame.buffer().dimensions())
+    }
+
+    pub fn encode_gif(self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        {
+            // speed 10 is a lot faster than the default and still looks fine
+            let mut encoder = GifEncoder::new_with_speed(&mut buffer, 10);
+            encoder.set_repeat(self.repeat)?;
+            encoder.encode_frames(self.frames)?;
+        }
+        Ok(buffer)
+    }
+}
+
+/// keeps the first frame of every `step` frames, it lasts as long as the whole group
+fn merge_frames(frames: Vec<Frame>, step: usize) -> Vec<Frame> {
+    frames
+        .chunks(step)
+        .map(|chunk| {
+            let duration: Duration = chunk.iter().map(|frame| Duration::from(frame.delay())).sum();
+            let first = &chunk[0];
+            Frame::from_parts(first.buffer().clone(), first.left(), first.top(), Delay::from_saturating_duration(duration))
+        })
+        .collect()
+}
+
+fn pixels(frames: &[Frame]) -> usize {
+    frames.iter().map(|frame| frame.buffer().width() as usize * frame.buffer().height() as usize).sum()
+}
+
+/// decodes the frames and returns them with the number of frames in the file.
+/// Without skip_frames more than max_frames are rejected, with it at most twice max_frames are
+/// kept in memory by merging neighbours, so skip_frames afterwards still picks evenly.
+/// The kept frames are bounded by MAX_TOTAL_MEGAPIXELS
+fn collect_frames<'a>(decoder: impl AnimationDecoder<'a>, max_frames: usize, skip_frames: bool) -> Result<(Vec<Frame>, usize)> {
+    let max_frames = max_frames.max(1);
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut source_frames = 0;
+    // every step-th frame of the file is kept, the ones between only add their delay
+    let mut step = 1;
+    for frame in decoder.into_frames() {
+        if source_frames >= max_frames && !skip_frames {
+            bail!("This animation has more than {max_frames} frames, I only convert up to {max_frames}.");
+        }
+        let frame = frame?;
+        match frames.pop() {
+            Some(last) if source_frames % step != 0 => {
+                let duration = Duration::from(last.delay()) + Duration::from(frame.delay());
+                let (left, top) = (last.left(), last.top());
+                frames.push(Frame::from_parts(last.into_buffer(), left, top, Delay::from_saturating_duration(duration)));
+            }
+            last => {
+                frames.extend(last);
+                frames.push(frame);
+            }
+        }
+        source_frames += 1;
+        if frames.len() >= 2 * max_frames {
+            frames = merge_frames(frames, 2);
+            step *= 2;
+        }
+        if pixels(&frames) > MAX_TOTAL_MEGAPIXELS * 1_000_000 {
+            bail!("This animation is too big for me, try a smaller or shorter one.");
+        }
+    }
+    Ok((frames, source_frames))
+}
+
+/// reads the loop count from the NETSCAPE2.0 application extension
+fn read_repeat(bytes: &[u8]) -> Repeat {
+    let marker = b"NETSCAPE2.0";
+    let position = bytes.windows(marker.len()).position(|window| window == marker);
+    match position {
+        Some(i) if bytes.len() > i + marker.len() + 3 => {
+            // skip sub-block size and sub-block id
+            let start = i + marker.len() + 2;
+            let count = u16::from_le_bytes([bytes[start], bytes[start + 1]]);
+            if count == 0 { Repeat::Infinite } else { Repeat::Finite(count) }
+        }
+        _ => Repeat::Finite(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::cell::RefCell;
+
+    /// a GIF with `count` frames of 8x8 pixels, every frame a bit brighter
+    fn gif(count: usize) -> Vec<u8> {
+        let frames = (0..count)
+            .map(|i| {
+                let value = (i * 255 / count.max(1)) as u8;
+                let image = RgbaImage::from_pixel(8, 8, Rgba([value, value, value, 255]));
+                Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(100, 1))
+            })
+            .collect();
+        Animation { frames, repeat: Repeat::Infinite, source_frames: count }.encode_gif().unwrap()
+    }
+
+    #[test]
+    fn progress_is_reported_every_few_frames() {
+        let animation = Animation::decode_gif(&gif(25), 100, false).unwrap();
+        assert_eq!(animation.frames.len(), 25);
+        let reports = RefCell::new(Vec::new());
+        let animation = animation.map_frames(|image| image, |done, total| reports.borrow_mut().push((done, total)));
+        assert_eq!(animation.frames.len(), 25);
+        assert_eq!(reports.into_inner(), vec![(0, 25), (10, 25), (20, 25)]);
+    }
+}
@@ -3,6 +3,7 @@ use image::{DynamicImage, Rgba, RgbaImage};
 use imageproc::drawing::{draw_text_mut, Canvas};
 use image::imageops::overlay;
 use lazy_static::lazy_static;
+use log::debug;
 use std::sync::Mutex;
 
 
@@ -67,10 +68,9 @@ pub fn generate_image(
         color
     ).unwrap() as i32 + y_font_offset;
 
-    print!("start y: {} ", start_y);
     // put text image onto TP image
     overlay(&mut image, &text_overlay, (bar_pos).into(), start_y.into());
-    println!("Creating image {:?}", start.elapsed());
+    debug!("Created brightness scale elapsed_ms={}", start.elapsed().as_millis());
     image
 }
 
@@ -0,0 +1,73 @@
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    colors::{self, NordOptions, Palette, PaletteColors}, config::load_config, encode::{encode_image_with, EncodeOptions, OutputFormat}, exif
+};
+
+
+const USAGE: &str = "Usage: image2nord convert <input> <output> [--palette <name>] [--strength <0.0 - 1.0>] [--scale <0.25 - 2.0>]";
+
+/// converts a local file the same way the bot does, the output format follows the extension
+pub fn convert(args: &[String]) -> Result<()> {
+    let mut paths = Vec::new();
+    let mut palette = Palette::Nord;
+    let mut strength = 1.0;
+    let mut scale = 1.0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palette" => {
+                let value = args.next().context(USAGE)?;
+                palette = value.to_lowercase().parse::<Palette>()
+                    .with_context(|| format!("Unknown palette: {value}"))?;
+            }
+            "--strength" => {
+                let value = args.next().context(USAGE)?;
+                strength = value.parse::<f32>()
+                    .with_context(|| format!("Strength is not a number: {value}"))?
+                    .clamp(0.0, 1.0);
+            }
+            "--scale" => {
+                let value = args.next().context(USAGE)?;
+                scale = value.parse::<f32>()
+                    .with_context(|| format!("Scale is not a number: {value}"))?;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let [input, output] = paths[..] else {
+        bail!(USAGE);
+    };
+
+    let format = match output.rsplit('.').next().map(|extension| extension.to_lowercase()).as_deref() {
+        Some("png") => OutputFormat::Png,
+        Some("jpg") | Some("jpeg") => OutputFormat::Jpeg(85),
+        Some("webp") => OutputFormat::WebP,
+        _ => bail!("Can't tell the output format of {output}, use .png, .jpg or .webp"),
+    };
+
+    let bytes = std::fs::read(input).with_context(|| format!("Failed to read {input}"))?;
+    let image = image::load_from_memory(&bytes).with_context(|| format!("Failed to load image {input}"))?;
+    let image = match exif::read_orientation(&bytes) {
+        Some(orientation) => exif::apply_orientation(image, orientation),
+        None => image,
+    };
+    let max_dimension = load_config().limits.max_dimension;
+    let image = colors::fit_within(image, max_dimension);
+
+    let info = colors::calculate_average_brightness(&image.to_rgba8());
+    let mut options = NordOptions::from_image_information(&info);
+    options.start = true;
+    options.palette = palette;
+    options.strength = strength;
+    let image = colors::apply_nord(image, options, &info);
+    let image = colors::scale_output(image, scale, max_dimension);
+
+    let encode_options = EncodeOptions {
+        background: PaletteColors::from_palette(palette).background(),
+        png_compression: load_config().output.png_compression,
+    };
+    let buffer = encode_image_with(&image, format, encode_options)?;
+    std::fs::write(output, buffer).with_context(|| format!("Failed to write {output}"))?;
+    Ok(())
+}
@@ -1,14 +1,19 @@
-use image::{DynamicImage, GenericImageView, RgbaImage, Rgb, Rgba};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage, Luma, RgbaImage, Rgb, Rgba};
 use imageproc::filter::gaussian_blur_f32;
 use onnxruntime::session::Session;
 use serenity::all::{ButtonStyle, CreateActionRow, CreateButton, ReactionType};
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
 use std::vec;
 use onnxruntime::{environment::Environment, ndarray::Array4, tensor::OrtOwnedTensor, GraphOptimizationLevel};
 use ndarray;
 use derivative::Derivative;
+use log::debug;
+use rayon::prelude::*;
 
-use crate::config::load_config;
+use crate::config::{load_config, GradientConfig};
+use crate::encode::OutputFormat;
 
 #[derive(Clone, Debug)]
 pub enum ImageType {
@@ -121,6 +126,131 @@ impl ActivationFunction {
         values[next]
     }
 }
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum DistanceMetric {
+    Brightness,
+    RgbEuclidean,
+    WeightedRgb,
+    Ciede2000,
+    /// hue first, so warm colors stay warm and cool colors stay cool
+    Hsl,
+}
+impl DistanceMetric {
+    pub fn iter() -> Vec<DistanceMetric> {
+        (0..5).filter_map(DistanceMetric::from_u8).collect()
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DistanceMetric::Brightness),
+            1 => Some(DistanceMetric::RgbEuclidean),
+            2 => Some(DistanceMetric::WeightedRgb),
+            3 => Some(DistanceMetric::Ciede2000),
+            4 => Some(DistanceMetric::Hsl),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            DistanceMetric::Brightness => "Brightness",
+            DistanceMetric::RgbEuclidean => "RGB",
+            DistanceMetric::WeightedRgb => "Weighted RGB",
+            DistanceMetric::Ciede2000 => "CIEDE2000",
+            DistanceMetric::Hsl => "HSL",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        DistanceMetric::from_u8((*self as u8 + 1) % 5).unwrap()
+    }
+
+    pub fn distance(&self, c1: &RgbColor, c2: &RgbColor) -> f32 {
+        match self {
+            DistanceMetric::Brightness => (c1.brightness() - c2.brightness()).abs(),
+            DistanceMetric::RgbEuclidean => c1.color_distance((c2.r, c2.g, c2.b)),
+            DistanceMetric::WeightedRgb => {
+                // "redmean" approximation of human perception
+                let r_mean = (c1.r as f32 + c2.r as f32) / 2.0;
+                let dr = c1.r as f32 - c2.r as f32;
+                let dg = c1.g as f32 - c2.g as f32;
+                let db = c1.b as f32 - c2.b as f32;
+                ((2.0 + r_mean / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - r_mean) / 256.0) * db * db).sqrt()
+            },
+            DistanceMetric::Ciede2000 => ciede2000(c1.to_lab(), c2.to_lab()),
+            DistanceMetric::Hsl => {
+                let (h1, s1, l1) = c1.to_hsl();
+                let (h2, s2, l2) = c2.to_hsl();
+                let hue_difference = (h1 - h2).abs();
+                let hue_difference = hue_difference.min(360.0 - hue_difference) / 180.0;
+                // the hue of greyish colors is meaningless
+                let colorfulness = (s1 * s2).sqrt();
+                3.0 * hue_difference * colorfulness + (s1 - s2).abs() + 0.5 * (l1 - l2).abs()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    /// bayer matrix with the given size and strength in percent, 
+    /// subtle strengths suit flat art like screenshots
+    Ordered(u8, u8),
+    FloydSteinberg,
+    /// mixes the two nearest palette colors by their distances, smooth but without any pattern
+    TwoColor,
+}
+impl Dither {
+    /// key used in custom ids
+    pub fn as_str(&self) -> String {
+        match self {
+            Dither::None => String::from("no"),
+            Dither::Ordered(size, 100) => format!("o{size}"),
+            Dither::Ordered(size, strength) => format!("o{size}s{strength}"),
+            Dither::FloydSteinberg => String::from("fs"),
+            Dither::TwoColor => String::from("tc"),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Dither::None => "Off",
+            Dither::Ordered(..) => "Ordered",
+            Dither::FloydSteinberg => "Floyd-Steinberg",
+            Dither::TwoColor => "Two Color Blend",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Dither::None => Dither::Ordered(8, 100),
+            Dither::Ordered(..) => Dither::FloydSteinberg,
+            Dither::FloydSteinberg => Dither::TwoColor,
+            Dither::TwoColor => Dither::None,
+        }
+    }
+}
+
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    /// parses the keys of custom ids
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let ordered = |value: &str| {
+            let ordered = value.strip_prefix("o")?;
+            let (size, strength) = ordered.split_once('s').unwrap_or((ordered, "100"));
+            Some(Dither::Ordered(size.parse::<u8>().ok()?, strength.parse::<u8>().ok()?.min(100)))
+        };
+        match value {
+            "no" => Ok(Dither::None),
+            "fs" => Ok(Dither::FloydSteinberg),
+            "tc" => Ok(Dither::TwoColor),
+            _ => ordered(value).ok_or_else(|| anyhow::anyhow!("Unknown dither: {value}")),
+        }
+    }
+}
+
 // implement clone
 #[derive(Clone, Debug)]
 
@@ -140,6 +270,302 @@ impl NordPreset {
 }
 
 
+/// the built in color schemes
+///
+/// ```
+/// use image2nord::colors::Palette;
+///
+/// assert_eq!("gruvbox".parse::<Palette>().ok(), Some(Palette::Gruvbox));
+/// assert_eq!(Palette::Nord.next(), Palette::NordLight);
+/// ```
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum Palette {
+    Nord,
+    /// Snow Storm backgrounds with Polar Night details, for lightening dark images
+    #[name = "Nord Light"]
+    NordLight,
+    Gruvbox,
+    Dracula,
+    #[name = "Solarized Dark"]
+    SolarizedDark,
+    /// light as well, prompts offer it for dark images
+    #[name = "Solarized Light"]
+    SolarizedLight,
+    #[name = "Catppuccin Mocha"]
+    CatppuccinMocha,
+    #[name = "Catppuccin Macchiato"]
+    CatppuccinMacchiato,
+    #[name = "Catppuccin Frappé"]
+    CatppuccinFrappe,
+    /// the light flavor, so bright results are wanted here
+    #[name = "Catppuccin Latte"]
+    CatppuccinLatte,
+    /// the colors a server set with /set_custom_palette
+    Custom,
+}
+
+impl Palette {
+    pub fn iter() -> Vec<Palette> {
+        vec![
+            Palette::Nord, Palette::NordLight, Palette::Gruvbox, Palette::Dracula, Palette::SolarizedDark, Palette::SolarizedLight,
+            Palette::CatppuccinMocha, Palette::CatppuccinMacchiato, Palette::CatppuccinFrappe, Palette::CatppuccinLatte,
+            Palette::Custom,
+        ]
+    }
+
+    /// key used in custom ids and command arguments
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Palette::Nord => "nord",
+            Palette::NordLight => "nordlight",
+            Palette::Gruvbox => "gruvbox",
+            Palette::Dracula => "dracula",
+            Palette::SolarizedDark => "solarizeddark",
+            Palette::SolarizedLight => "solarizedlight",
+            Palette::CatppuccinMocha => "mocha",
+            Palette::CatppuccinMacchiato => "macchiato",
+            Palette::CatppuccinFrappe => "frappe",
+            Palette::CatppuccinLatte => "latte",
+            Palette::Custom => "custom",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Nord => "Nord",
+            Palette::NordLight => "Nord Light",
+            Palette::Gruvbox => "Gruvbox",
+            Palette::Dracula => "Dracula",
+            Palette::SolarizedDark => "Solarized Dark",
+            Palette::SolarizedLight => "Solarized Light",
+            Palette::CatppuccinMocha => "Catppuccin Mocha",
+            Palette::CatppuccinMacchiato => "Catppuccin Macchiato",
+            Palette::CatppuccinFrappe => "Catppuccin Frappé",
+            Palette::CatppuccinLatte => "Catppuccin Latte",
+            Palette::Custom => "Custom",
+        }
+    }
+
+    /// light palettes map onto bright backgrounds, so their results aren't capped in brightness
+    pub fn is_light(&self) -> bool {
+        matches!(self, Palette::NordLight | Palette::SolarizedLight | Palette::CatppuccinLatte)
+    }
+
+    pub fn next(&self) -> Self {
+        let palettes = Palette::iter();
+        let i = palettes.iter().position(|palette| palette == self).unwrap_or(0);
+        palettes[(i + 1) % palettes.len()]
+    }
+
+    /// dark background colors, darkest first
+    pub fn darks(&self) -> &'static [RgbColor] {
+        match self {
+            Palette::Nord => &NORD_POLAR_NIGHT,
+            Palette::NordLight => &NORD_SNOW_STORM,
+            Palette::Gruvbox => &GRUVBOX_BACKGROUND,
+            Palette::Dracula => &DRACULA_BACKGROUND,
+            Palette::SolarizedDark => &SOLARIZED_BASE_DARK,
+            Palette::SolarizedLight => &SOLARIZED_LIGHT_BACKGROUND,
+            Palette::CatppuccinMocha => &MOCHA_BACKGROUND,
+            Palette::CatppuccinMacchiato => &MACCHIATO_BACKGROUND,
+            Palette::CatppuccinFrappe => &FRAPPE_BACKGROUND,
+            Palette::CatppuccinLatte => &LATTE_BACKGROUND,
+            // custom colors live per guild, see PaletteColors
+            Palette::Custom => &[],
+        }
+    }
+
+    /// light foreground colors, darkest first
+    pub fn lights(&self) -> &'static [RgbColor] {
+        match self {
+            Palette::Nord => &NORD_SNOW_STORM,
+            Palette::NordLight => &NORD_POLAR_NIGHT,
+            Palette::Gruvbox => &GRUVBOX_FOREGROUND,
+            Palette::Dracula => &DRACULA_FOREGROUND,
+            Palette::SolarizedDark => &SOLARIZED_BASE_LIGHT,
+            Palette::SolarizedLight => &SOLARIZED_LIGHT_FOREGROUND,
+            Palette::CatppuccinMocha => &MOCHA_FOREGROUND,
+            Palette::CatppuccinMacchiato => &MACCHIATO_FOREGROUND,
+            Palette::CatppuccinFrappe => &FRAPPE_FOREGROUND,
+            Palette::CatppuccinLatte => &LATTE_FOREGROUND,
+            Palette::Custom => &[],
+        }
+    }
+
+    /// cool colors which replace colorful pixels
+    pub fn accents(&self) -> &'static [RgbColor] {
+        match self {
+            Palette::Nord | Palette::NordLight => &NORD_FROST,
+            Palette::Gruvbox => &GRUVBOX_COOL,
+            Palette::Dracula => &DRACULA_COOL,
+            Palette::SolarizedDark => &SOLARIZED_COOL,
+            Palette::SolarizedLight => &SOLARIZED_COOL,
+            Palette::CatppuccinMocha => &MOCHA_COOL,
+            Palette::CatppuccinMacchiato => &MACCHIATO_COOL,
+            Palette::CatppuccinFrappe => &FRAPPE_COOL,
+            Palette::CatppuccinLatte => &LATTE_COOL,
+            Palette::Custom => &[],
+        }
+    }
+
+    /// warm signal colors
+    pub fn highlights(&self) -> &'static [RgbColor] {
+        match self {
+            Palette::Nord | Palette::NordLight => &NORD_AURORA,
+            Palette::Gruvbox => &GRUVBOX_WARM,
+            Palette::Dracula => &DRACULA_WARM,
+            Palette::SolarizedDark => &SOLARIZED_WARM,
+            Palette::SolarizedLight => &SOLARIZED_WARM,
+            Palette::CatppuccinMocha => &MOCHA_WARM,
+            Palette::CatppuccinMacchiato => &MACCHIATO_WARM,
+            Palette::CatppuccinFrappe => &FRAPPE_WARM,
+            Palette::CatppuccinLatte => &LATTE_WARM,
+            Palette::Custom => &[],
+        }
+    }
+
+    /// every color of the palette
+    pub fn colors(&self) -> Vec<RgbColor> {
+        [self.darks(), self.lights(), self.accents(), self.highlights()].concat()
+    }
+}
+
+impl FromStr for Palette {
+    type Err = anyhow::Error;
+
+    /// parses the keys of custom ids and command arguments
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        Palette::iter()
+            .into_iter()
+            .find(|palette| palette.as_str() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown palette: {value}"))
+    }
+}
+
+/// the color groups of Nord, the other palettes have the same roles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorGroup {
+    /// dark backgrounds
+    PolarNight,
+    /// light foregrounds
+    SnowStorm,
+    /// cool accents
+    Frost,
+    /// warm highlights
+    Aurora,
+}
+
+impl ColorGroup {
+    pub const ALL: [ColorGroup; 4] = [ColorGroup::PolarNight, ColorGroup::SnowStorm, ColorGroup::Frost, ColorGroup::Aurora];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorGroup::PolarNight => "polar_night",
+            ColorGroup::SnowStorm => "snow_storm",
+            ColorGroup::Frost => "frost",
+            ColorGroup::Aurora => "aurora",
+        }
+    }
+
+    /// light palettes have their backgrounds in lights, Polar Night stays the dark group for them as well
+    pub fn colors(&self, palette: Palette) -> &'static [RgbColor] {
+        let (dark, light) = if palette.is_light() {
+            (palette.lights(), palette.darks())
+        } else {
+            (palette.darks(), palette.lights())
+        };
+        match self {
+            ColorGroup::PolarNight => dark,
+            ColorGroup::SnowStorm => light,
+            ColorGroup::Frost => palette.accents(),
+            ColorGroup::Aurora => palette.highlights(),
+        }
+    }
+}
+
+impl FromStr for ColorGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        ColorGroup::ALL
+            .into_iter()
+            .find(|group| group.as_str() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown color group: {value}"))
+    }
+}
+
+/// the colors pixels get matched against
+#[derive(Clone, Debug)]
+pub struct PaletteColors {
+    pub darks: Vec<RgbColor>,
+    pub accents: Vec<RgbColor>,
+    /// grey colors sorted from dark to light, used by the grayscale mode
+    pub neutrals: Vec<RgbColor>,
+    /// accents and warm highlights, the HSL metric picks from all hues
+    pub colorful: Vec<RgbColor>,
+}
+
+impl PaletteColors {
+    /// falls back to Nord for the custom palette, which only exists per guild
+    pub fn from_palette(palette: Palette) -> Self {
+        let palette = if palette == Palette::Custom { Palette::Nord } else { palette };
+        let mut neutrals = [palette.darks(), palette.lights()].concat();
+        neutrals.sort_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness()));
+        PaletteColors {
+            darks: palette.darks().to_vec(),
+            accents: palette.accents().to_vec(),
+            neutrals,
+            colorful: [palette.accents(), palette.highlights()].concat(),
+        }
+    }
+
+    /// only keeps the colors of `groups`, lists which end up empty take all kept colors instead.
+    /// None if fewer than two colors are left, there is nothing to map onto then
+    pub fn from_palette_groups(palette: Palette, groups: &[ColorGroup]) -> Option<Self> {
+        let kept: Vec<RgbColor> = groups.iter().flat_map(|group| group.colors(palette).iter().cloned()).collect();
+        if kept.len() < 2 {
+            return None;
+        }
+        let all = PaletteColors::from_palette(palette);
+        let filter = |colors: Vec<RgbColor>| {
+            let colors: Vec<RgbColor> = colors.into_iter().filter(|color| kept.contains(color)).collect();
+            if colors.is_empty() { kept.clone() } else { colors }
+        };
+        let mut neutrals = filter(all.neutrals);
+        neutrals.sort_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness()));
+        Some(PaletteColors {
+            darks: filter(all.darks),
+            accents: filter(all.accents),
+            neutrals,
+            colorful: filter(all.colorful),
+        })
+    }
+
+    /// custom colors aren't grouped, so greyish and colorful pixels both pick from all of them
+    pub fn from_rgb(colors: &[[u8; 3]]) -> Self {
+        let colors: Vec<RgbColor> = colors.iter().map(|&[r, g, b]| RgbColor::new(r, g, b)).collect();
+        let mut neutrals: Vec<RgbColor> = colors
+            .iter()
+            .filter(|color| color.calculate_grayscale_similarity() < 0.1)
+            .cloned()
+            .collect();
+        if neutrals.is_empty() {
+            neutrals = colors.clone();
+        }
+        neutrals.sort_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness()));
+        PaletteColors { darks: colors.clone(), accents: colors.clone(), neutrals, colorful: colors }
+    }
+
+    /// the darkest neutral, transparent areas are flattened onto it for formats without alpha
+    pub fn background(&self) -> [u8; 3] {
+        self.neutrals.first().map_or(crate::encode::BACKGROUND, |color| {
+            let Rgba([r, g, b, _]) = color.to_rgba();
+            [r, g, b]
+        })
+    }
+}
+
+
 #[derive(Clone, Debug, Derivative)]
 #[derivative(PartialEq)]
 pub struct NordOptions {
@@ -160,6 +586,62 @@ pub struct NordOptions {
     
     pub model: Models,
     pub activation_function: ActivationFunction,
+
+    #[derivative(PartialEq = "ignore")]
+    pub palette: Palette,
+
+    #[derivative(PartialEq = "ignore")]
+    pub format: OutputFormat,
+
+    #[derivative(PartialEq = "ignore")]
+    pub metric: DistanceMetric,
+
+    #[derivative(PartialEq = "ignore")]
+    pub dither: Dither,
+
+    /// how much of the conversion is mixed into the original (0.0 - 1.0)
+    #[derivative(PartialEq = "ignore")]
+    pub strength: f32,
+
+    /// keeps the brightness of every pixel and only takes the hue of the palette
+    #[derivative(PartialEq = "ignore")]
+    pub preserve_luminance: bool,
+
+    /// also sends the original and the conversion side by side
+    #[derivative(PartialEq = "ignore")]
+    pub compare: bool,
+
+    /// maps the brightness of every pixel onto the neutral colors of the palette
+    #[derivative(PartialEq = "ignore")]
+    pub grayscale: bool,
+
+    /// stretches the histogram before converting
+    #[derivative(PartialEq = "ignore")]
+    pub auto_contrast: bool,
+
+    /// only lowers the brightness and keeps the colors, replaces every other filter
+    pub dim: bool,
+
+    /// maps the luminance through a ramp of palette colors instead of picking the nearest color
+    #[derivative(PartialEq = "ignore")]
+    pub gradient_map: bool,
+
+    /// indices of the palette colors the gradient map runs through, gradient.ramp of the config
+    #[derivative(PartialEq = "ignore")]
+    pub gradient_ramp: Vec<usize>,
+
+    /// only pixels brighter than BRIGHT_ONLY_THRESHOLD take the conversion, dark areas stay as they are
+    #[derivative(PartialEq = "ignore")]
+    pub bright_only: bool,
+
+    /// smooths sensor noise with smooth_noise before converting, so photos don't turn into confetti
+    #[derivative(PartialEq = "ignore")]
+    pub smooth: bool,
+
+    /// every pixel becomes exactly one of the palette colors, nothing is blended or mixed with the original.
+    /// Restricted color groups need this, otherwise colors outside of them would show up
+    #[derivative(PartialEq = "ignore")]
+    pub snap: bool,
 }
 
 impl NordOptions {
@@ -180,11 +662,49 @@ impl NordOptions {
             start: false,
             model: Models::Algorithm,
             activation_function: ActivationFunction::Sigmoid,
+            palette: Palette::Nord,
+            format: OutputFormat::WebP,
+            metric: DistanceMetric::Brightness,
+            dither: Dither::None,
+            strength: 1.0,
+            preserve_luminance: false,
+            compare: false,
+            grayscale: false,
+            auto_contrast: false,
+            dim: false,
+            gradient_map: false,
+            gradient_ramp: GradientConfig::default().ramp,
+            bright_only: false,
+            smooth: false,
+            snap: false,
+        }
+    }
+
+    /// takes over the settings which presets and auto adjust don't touch
+    pub fn with_settings_of(self, other: &NordOptions) -> Self {
+        NordOptions {
+            palette: other.palette,
+            format: other.format,
+            metric: other.metric,
+            dither: other.dither,
+            strength: other.strength,
+            preserve_luminance: other.preserve_luminance,
+            compare: other.compare,
+            grayscale: other.grayscale,
+            auto_contrast: other.auto_contrast,
+            gradient_map: other.gradient_map,
+            gradient_ramp: other.gradient_ramp.clone(),
+            bright_only: other.bright_only,
+            smooth: other.smooth,
+            snap: other.snap,
+            ..self
         }
     }
 
     pub fn from_image_information(image_information: &ImageInformation) -> Self {
         let mut options = NordOptions::default();
+        // tuned when the brightness was the mean of the channels, L* of a gray stays within 0.04 of that,
+        // so 0.5 still splits the images near mid gray
         let invert_by_brightness = image_information.brightness.average > 0.5;
         let is_probably_anime = |info: &ImageInformation| -> bool {
             info.color_map.most_present_color_percentage > 0.005 && info.grayscale_similarity.average > 0.06
@@ -257,6 +777,21 @@ impl NordOptions {
                     start: false,
                     model: Models::Algorithm,
                     activation_function: ActivationFunction::Sigmoid,
+                    palette: Palette::Nord,
+                    format: OutputFormat::WebP,
+                    metric: DistanceMetric::Brightness,
+                    dither: Dither::None,
+                    strength: 1.0,
+                    preserve_luminance: false,
+                    compare: false,
+                    grayscale: false,
+                    auto_contrast: false,
+                    dim: false,
+                    gradient_map: false,
+                    gradient_ramp: GradientConfig::default().ramp,
+                    bright_only: false,
+                    smooth: false,
+                    snap: false,
                 }
             },
             NordPreset::DynamicBackground => {
@@ -271,6 +806,21 @@ impl NordOptions {
                     start: false,
                     model: Models::IsnetGeneral,
                     activation_function: ActivationFunction::Sigmoid,
+                    palette: Palette::Nord,
+                    format: OutputFormat::WebP,
+                    metric: DistanceMetric::Brightness,
+                    dither: Dither::None,
+                    strength: 1.0,
+                    preserve_luminance: false,
+                    compare: false,
+                    grayscale: false,
+                    auto_contrast: false,
+                    dim: false,
+                    gradient_map: false,
+                    gradient_ramp: GradientConfig::default().ramp,
+                    bright_only: false,
+                    smooth: false,
+                    snap: false,
                 }
             }
         }
@@ -290,43 +840,104 @@ impl NordOptions {
     }
 
 
+    fn mode_bits(&self) -> u8 {
+        [
+            (self.preserve_luminance, MODE_PRESERVE_LUMINANCE),
+            (self.compare, MODE_COMPARE),
+            (self.grayscale, MODE_GRAYSCALE),
+            (self.auto_contrast, MODE_AUTO_CONTRAST),
+            (self.dim, MODE_DIM),
+            (self.gradient_map, MODE_GRADIENT_MAP),
+            (self.bright_only, MODE_BRIGHT_ONLY),
+            (self.smooth, MODE_SMOOTH),
+        ]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(0, |bits, (_, bit)| bits | bit)
+    }
+
     pub fn make_nord_custom_id(&self, message_id: &u64, update: bool, id: Option<usize>) -> String {
         // id is needed to make the custom id unique since there could be buttons which do the same
-        format!(
-            "darken-{}-{}-{}-{}-{}-{}-{:.2}-{}-{}-{}-{}-{}-{}", 
-            update, self.invert, self.hue_rotate, 
-            self.sepia, self.nord, self.erase_most_present_color, 
-            self.erase_when_percentage, self.auto_adjust, 
-            self.start, self.model.to_struct().id, self.activation_function as u8,
+        // flags are encoded as 0/1 and the modes as bits since discord limits custom ids to 100 characters
+        let custom_id = format!(
+            "darken-{}-{}-{}-{}-{}-{:x}-{}-{}-{}-{}-{}-{}-{:.2}-{}-{}-{}-{}-{}-{}", 
+            self.palette.as_str(), self.format.as_str(), self.metric as u8, 
+            self.dither.as_str(), (self.strength * 100.0).round() as u8, self.mode_bits(), 
+            flag(update), flag(self.invert), self.hue_rotate, 
+            flag(self.sepia), flag(self.nord), flag(self.erase_most_present_color), 
+            self.erase_when_percentage, flag(self.auto_adjust), 
+            flag(self.start), self.model.to_struct().id, self.activation_function as u8,
             id.unwrap_or(0), message_id
-        )
+        );
+        debug_assert!(custom_id.len() <= CUSTOM_ID_MAX_LEN, "custom id is too long for discord: {custom_id}");
+        custom_id
     }
-    
-    pub fn from_custom_id(custom_id: &str) -> Self {
+
+}
+
+/// discord rejects buttons with longer custom ids
+pub const CUSTOM_ID_MAX_LEN: usize = 100;
+
+/// everything a button made by make_nord_custom_id carries
+#[derive(Clone, Debug)]
+pub struct NordButton {
+    pub options: NordOptions,
+    /// only the 🌙 button of a prompt is no update
+    pub update: bool,
+    pub id: usize,
+    /// the message with the image
+    pub message_id: u64,
+}
+
+impl NordButton {
+    /// None for custom ids which are too short or malformed, like the ones of buttons from older versions
+    pub fn decode(custom_id: &str) -> Option<Self> {
         let mut parts = custom_id.split("-").skip(1);
-        let _update = parts.next().unwrap().parse::<bool>().unwrap();
-        let invert = parts.next().unwrap().parse::<bool>().unwrap();
-        let hue_rotate = parts.next().unwrap().parse::<f32>().unwrap();
-        let sepia = parts.next().unwrap().parse::<bool>().unwrap();
-        let nord = parts.next().unwrap().parse::<bool>().unwrap();
-        let erase_most_present_color = parts.next().unwrap().parse::<bool>().unwrap();
-        let erase_when_percentage = parts.next().unwrap().parse::<f64>().unwrap();
-        let auto_adjust = parts.next().unwrap().parse::<bool>().unwrap();
-        let start = parts.next().unwrap().parse::<bool>().unwrap();
-        let model_id: usize = parts.next().unwrap().parse::<usize>().unwrap();
-        let model = Models::from_id(model_id);
-        let activation_function_id = parts.next().unwrap().parse::<u8>().unwrap();
-        let activation_function = ActivationFunction::from_u8(activation_function_id)
-            .expect(&format!("Invalid ActivationFunction ID: {}", activation_function_id));
-        let _id = parts.next().unwrap().parse::<usize>().unwrap();
-        let _message_id = parts.next().unwrap().parse::<u64>().unwrap();
-        NordOptions {
+        let mut next = || parts.next();
+        let palette = next()?.parse::<Palette>().unwrap_or(Palette::Nord);
+        let format = next()?.parse::<OutputFormat>().unwrap_or(OutputFormat::WebP);
+        let metric = next()?.parse::<u8>().ok()
+            .and_then(DistanceMetric::from_u8)
+            .unwrap_or(DistanceMetric::Brightness);
+        let dither = next()?.parse::<Dither>().unwrap_or(Dither::None);
+        let strength = next()?.parse::<u8>().map(|percent| percent as f32 / 100.0).unwrap_or(1.0);
+        let modes = u8::from_str_radix(next()?, 16).unwrap_or(0);
+        let mode = |bit: u8| modes & bit != 0;
+        let (preserve_luminance, compare, grayscale, auto_contrast, dim, gradient_map, bright_only, smooth) = (
+            mode(MODE_PRESERVE_LUMINANCE), mode(MODE_COMPARE), mode(MODE_GRAYSCALE), 
+            mode(MODE_AUTO_CONTRAST), mode(MODE_DIM), mode(MODE_GRADIENT_MAP), mode(MODE_BRIGHT_ONLY),
+            mode(MODE_SMOOTH)
+        );
+        let update = parse_flag(next()?);
+        let invert = parse_flag(next()?);
+        let hue_rotate = next()?.parse::<f32>().ok()?;
+        let sepia = parse_flag(next()?);
+        let nord = parse_flag(next()?);
+        let erase_most_present_color = parse_flag(next()?);
+        let erase_when_percentage = next()?.parse::<f64>().ok()?;
+        let auto_adjust = parse_flag(next()?);
+        let start = parse_flag(next()?);
+        let model = Models::from_id(next()?.parse::<usize>().ok()?);
+        let activation_function = ActivationFunction::from_u8(next()?.parse::<u8>().ok()?)?;
+        let id = next()?.parse::<usize>().ok()?;
+        let message_id = next()?.parse::<u64>().ok()?;
+        let options = NordOptions {
             invert, hue_rotate, sepia, 
             nord, erase_most_present_color, 
             erase_when_percentage, auto_adjust, 
             start, model, activation_function,
-        }
+            palette, format, metric, dither, strength, 
+            preserve_luminance, compare, grayscale, auto_contrast, dim, gradient_map, bright_only, smooth,
+            // the ramp comes from the config of the bot, not the button
+            gradient_ramp: GradientConfig::default().ramp,
+            // color groups only come from /nord, the buttons never snap
+            snap: false,
+        };
+        Some(NordButton { options, update, id, message_id })
     }
+}
+
+impl NordOptions {
     pub fn build_componets(&self, message_id: u64, update: bool) -> Vec<CreateActionRow> {
         let mut components = Vec::new();
         let mut action_rows = Vec::<Vec<CreateButton>>::new();
@@ -338,6 +949,10 @@ impl NordOptions {
         };
 
         let function_name = format!("Mask Function: {}", self.activation_function.as_str());
+        let metric_name = format!("Match: {}", self.metric.as_str());
+        let palette_name = format!("Palette: {}", self.palette.name());
+        let dither_name = format!("Dither: {}", self.dither.name());
+        let try_palette = format!("Try {}", self.palette.next().name());
         // make option lists, so that the clicked button is inverted
         let option_2d_list: Vec<Vec<(&str, bool, NordOptions, bool)>> = vec![
             // component row
@@ -348,6 +963,7 @@ impl NordOptions {
                 ("Hue Rotate", if self.hue_rotate == 180. {true} else {false}, NordOptions {hue_rotate: if self.hue_rotate == 180. {0.} else {180.}, ..self_no_start}, true),
                 ("Sepia", self.sepia, NordOptions {sepia: !self.sepia, ..self_no_start}, true),
                 ("Nord", self.nord, NordOptions {nord: !self.nord, ..self_no_start}, true),
+                (&dither_name, self.dither != Dither::None, NordOptions {dither: self.dither.next(), ..self_no_start}, self.nord),
             ],
             vec![
                 ("Erase Background", self.erase_most_present_color, NordOptions {erase_most_present_color: !self.erase_most_present_color, ..self_no_start}, true),
@@ -357,14 +973,30 @@ impl NordOptions {
                 ("Anime", self.model == Models::IsnetAnime, NordOptions {model: Models::IsnetAnime, ..self_no_start}, is_model_enabled(self)),
                 (&function_name, true, NordOptions {activation_function: self.activation_function.next(), ..self_no_start}, is_model_enabled(self))
             ],
-            // preset vec
+            // a finished conversion can be run again right away, otherwise the presets
+            if self.start {
+                vec![
+                    (try_palette.as_str(), false, NordOptions {palette: self.palette.next(), ..self.clone()}, self.nord),
+                    ("Stronger", false, NordOptions {strength: (self.strength + STRENGTH_STEP).min(1.0), ..self.clone()}, self.strength < 1.0),
+                    ("Weaker", false, NordOptions {strength: (self.strength - STRENGTH_STEP).max(0.0), ..self.clone()}, self.strength > 0.0),
+                ]
+            } else {
+                vec![
+                    // dimming is the alternative to all presets
+                    ("Dim Only", self.dim, NordOptions {dim: !self.dim, ..self_no_start}, true),
+                    ("Nord w/ Color", self.is_preset(NordPreset::NordWithColor), NordOptions::from_preset(NordPreset::NordWithColor).with_settings_of(self), true),
+                    ("Nord w/o Color", self.is_preset(NordPreset::Nord), NordOptions::from_preset(NordPreset::Nord).with_settings_of(self), true),
+                    ("Static Background", self.is_preset(NordPreset::StaticBackground), NordOptions::from_preset(NordPreset::StaticBackground).with_settings_of(self), true),
+                    ("Dynamic Background", self.is_preset(NordPreset::DynamicBackground), NordOptions::from_preset(NordPreset::DynamicBackground).with_settings_of(self), true),
+                ]
+            },
+            // palette vec
             vec![
-                ("Presets:", self.is_any_preset(), NordOptions { ..self_no_start}, false),
-                ("Nord w/ Color", self.is_preset(NordPreset::NordWithColor), NordOptions::from_preset(NordPreset::NordWithColor), true),
-                ("Nord w/o Color", self.is_preset(NordPreset::Nord), NordOptions::from_preset(NordPreset::Nord), true),
-                ("Static Background", self.is_preset(NordPreset::StaticBackground), NordOptions::from_preset(NordPreset::StaticBackground), true),
-                ("Dynamic Background", self.is_preset(NordPreset::DynamicBackground), NordOptions::from_preset(NordPreset::DynamicBackground), true),
-            ]
+                (&palette_name, true, NordOptions {palette: self.palette.next(), ..self_no_start}, self.nord),
+                (&metric_name, true, NordOptions {metric: self.metric.next(), ..self_no_start}, self.nord),
+                ("Keep Luminance", self.preserve_luminance, NordOptions {preserve_luminance: !self.preserve_luminance, ..self_no_start}, self.nord),
+                ("Show Comparison", self.compare, NordOptions {compare: !self.compare, ..self_no_start}, true),
+            ],
         ];
 
         let mut name_to_color_map = HashMap::<&str, ButtonStyle>::new();
@@ -391,6 +1023,15 @@ impl NordOptions {
             }
             action_rows.push(action_row);
         }
+        // not an option, so it doesn't go through the custom id of the options
+        if let Some(palette_row) = action_rows.last_mut() {
+            palette_row.push(
+                CreateButton::new(format!("usage-{}-{}", self.palette.as_str(), message_id))
+                    .style(ButtonStyle::Secondary)
+                    .label("Show palette usage")
+                    .disabled(!self.nord)
+            );
+        }
         for action_row in action_rows {
             components.push(CreateActionRow::Buttons(action_row));
         }
@@ -406,8 +1047,22 @@ impl NordOptions {
                 .emoji("🗑️".parse::<ReactionType>().unwrap()),
             CreateButton::new(format!("clear-{}", message_id))
                 .style(ButtonStyle::Secondary)
-                .label("Keep both")
+                .label("Keep both"),
+            CreateButton::new(
+                NordOptions {format: self.format.next(), ..self_no_start}
+                    .make_nord_custom_id(&message_id, update, Some(99))
+            )
+                .style(ButtonStyle::Secondary)
+                .label(format!("Format: {}", self.format.name()))
         ];
+        // only converted images have something to compare
+        if self.start {
+            last_row.push(
+                CreateButton::new(format!("diff-{}", message_id))
+                    .style(ButtonStyle::Secondary)
+                    .label("Show diff")
+            );
+        }
         // add start button
         if !self.start {
             last_row.insert(0,
@@ -426,7 +1081,27 @@ impl NordOptions {
     }
 }
 
-#[derive(Clone, Debug)]
+/// how much the stronger and weaker buttons change the strength
+const STRENGTH_STEP: f32 = 0.25;
+
+const MODE_PRESERVE_LUMINANCE: u8 = 1;
+const MODE_COMPARE: u8 = 2;
+const MODE_GRAYSCALE: u8 = 4;
+const MODE_AUTO_CONTRAST: u8 = 8;
+const MODE_DIM: u8 = 16;
+const MODE_GRADIENT_MAP: u8 = 32;
+const MODE_BRIGHT_ONLY: u8 = 64;
+const MODE_SMOOTH: u8 = 128;
+
+fn flag(value: bool) -> u8 {
+    value as u8
+}
+
+fn parse_flag(value: &str) -> bool {
+    value == "1"
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RgbColor {
     r: u8,
     g: u8,
@@ -434,6 +1109,10 @@ pub struct RgbColor {
 }
 
 impl RgbColor {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        RgbColor { r, g, b }
+    }
+
     pub fn rn(&self) -> f32 {
         self.r as f32 / 255.0
     }
@@ -446,6 +1125,14 @@ impl RgbColor {
         self.b as f32 / 255.0
     }
 
+    pub fn to_rgba(&self) -> Rgba<u8> {
+        Rgba([self.r, self.g, self.b, 255])
+    }
+
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
     pub fn brightness(&self) -> f32 {
         (0.299 * self.r as f32 + 0.587 * self.g as f32 + 0.114 * self.b as f32) / 255.0
     }
@@ -489,6 +1176,52 @@ impl RgbColor {
             b: (new_b * 255.0) as u8,
         }
     }
+    /// hue in degrees, saturation and lightness from 0.0 to 1.0
+    pub fn to_hsl(&self) -> (f32, f32, f32) {
+        let (r, g, b) = (self.rn(), self.gn(), self.bn());
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        let delta = max - min;
+        if delta == 0.0 {
+            return (0.0, 0.0, lightness);
+        }
+        let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+        let hue = if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        (hue, saturation.min(1.0), lightness)
+    }
+
+    /// luminance of the linear light (0.0 - 1.0), unlike brightness this is how bright the color really is
+    pub fn relative_luminance(&self) -> f32 {
+        0.2126 * srgb_to_linear(self.rn()) + 0.7152 * srgb_to_linear(self.gn()) + 0.0722 * srgb_to_linear(self.bn())
+    }
+
+    /// CIE lightness of the Rec. 709 luminance (0.0 - 1.0), saturated blues count as dark and pale yellows as bright
+    pub fn perceived_lightness(&self) -> f32 {
+        let y = self.relative_luminance();
+        let f = if y > 0.008856 { y.cbrt() } else { 7.787 * y + 16.0 / 116.0 };
+        (116.0 * f - 16.0) / 100.0
+    }
+
+    /// converts sRGB to CIELAB (D65)
+    pub fn to_lab(&self) -> (f32, f32, f32) {
+        let (r, g, b) = (srgb_to_linear(self.rn()), srgb_to_linear(self.gn()), srgb_to_linear(self.bn()));
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+        let f = |t: f32| {
+            if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+    }
+
     fn color_distance(&self, c2: (u8, u8, u8)) -> f32 {
         let (r1, g1, b1) = (self.r, self.g, self.b);
         let (r2, g2, b2) = c2;
@@ -500,37 +1233,177 @@ impl RgbColor {
     
 }
 
-struct PolarNight {}
-impl PolarNight {
-    const A: RgbColor = RgbColor {r: 46, g: 52, b: 64};
-    const B: RgbColor = RgbColor {r: 59, g: 66, b: 82};
-    const C: RgbColor = RgbColor {r: 67, g: 76, b: 94};
-    const D: RgbColor = RgbColor {r: 76, g: 86, b: 106};
+/// lab values of the built in palettes are computed once, other colors on the fly
+fn palette_lab(color: &RgbColor) -> (f32, f32, f32) {
+    static BUILTIN: OnceLock<HashMap<RgbColor, (f32, f32, f32)>> = OnceLock::new();
+    let builtin = BUILTIN.get_or_init(|| {
+        Palette::iter().iter().flat_map(|palette| palette.colors()).map(|color| (color, color.to_lab())).collect()
+    });
+    builtin.get(color).copied().unwrap_or_else(|| color.to_lab())
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// CIEDE2000 color difference of two CIELAB colors
+fn ciede2000(lab1: (f32, f32, f32), lab2: (f32, f32, f32)) -> f32 {
+    let (l1, a1, b1) = lab1;
+    let (l2, a2, b2) = lab2;
+    let pow25_7 = 25f32.powi(7);
+
+    let c_mean = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + pow25_7)).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) }
+    };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f32| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(h_mean - 30.0) + 0.24 * cos(2.0 * h_mean)
+        + 0.32 * cos(3.0 * h_mean + 6.0) - 0.20 * cos(4.0 * h_mean - 63.0);
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + pow25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).max(0.0).sqrt()
+}
+
+const fn hex(code: u32) -> RgbColor {
+    RgbColor {r: (code >> 16) as u8, g: (code >> 8) as u8, b: code as u8}
 }
 
-// struct SnowStorm {}
-// impl SnowStorm {
-//     const A: RgbColor = RgbColor {r: 216, g: 222, b: 233};
-//     const B: RgbColor = RgbColor {r: 229, g: 233, b: 240};
-//     const C: RgbColor = RgbColor {r: 236, g: 239, b: 244};
-//     const D: RgbColor = RgbColor {r: 236, g: 239, b: 244};
-// }
+// https://www.nordtheme.com/docs/colors-and-palettes
+static NORD_POLAR_NIGHT: [RgbColor; 4] = [hex(0x2e3440), hex(0x3b4252), hex(0x434c5e), hex(0x4c566a)];
+static NORD_SNOW_STORM: [RgbColor; 3] = [hex(0xd8dee9), hex(0xe5e9f0), hex(0xeceff4)];
+static NORD_FROST: [RgbColor; 4] = [hex(0x8fbcbb), hex(0x88c0d0), hex(0x81a1c1), hex(0x5e81ac)];
+static NORD_AURORA: [RgbColor; 5] = [hex(0xbf616a), hex(0xd08770), hex(0xebcb8b), hex(0xa3be8c), hex(0xb48ead)];
+
+// https://github.com/morhetz/gruvbox (dark)
+static GRUVBOX_BACKGROUND: [RgbColor; 4] = [hex(0x282828), hex(0x3c3836), hex(0x504945), hex(0x665c54)];
+static GRUVBOX_FOREGROUND: [RgbColor; 4] = [hex(0xa89984), hex(0xbdae93), hex(0xd5c4a1), hex(0xebdbb2)];
+static GRUVBOX_COOL: [RgbColor; 4] = [hex(0x8ec07c), hex(0x83a598), hex(0xb8bb26), hex(0xd3869b)];
+static GRUVBOX_WARM: [RgbColor; 3] = [hex(0xfb4934), hex(0xfe8019), hex(0xfabd2f)];
+
+// https://draculatheme.com/contribute
+static DRACULA_BACKGROUND: [RgbColor; 3] = [hex(0x282a36), hex(0x44475a), hex(0x6272a4)];
+static DRACULA_FOREGROUND: [RgbColor; 1] = [hex(0xf8f8f2)];
+static DRACULA_COOL: [RgbColor; 4] = [hex(0x8be9fd), hex(0x50fa7b), hex(0xbd93f9), hex(0xff79c6)];
+static DRACULA_WARM: [RgbColor; 3] = [hex(0xff5555), hex(0xffb86c), hex(0xf1fa8c)];
+
+// https://ethanschoonover.com/solarized
+static SOLARIZED_BASE_DARK: [RgbColor; 4] = [hex(0x002b36), hex(0x073642), hex(0x586e75), hex(0x657b83)];
+static SOLARIZED_BASE_LIGHT: [RgbColor; 4] = [hex(0x839496), hex(0x93a1a1), hex(0xeee8d5), hex(0xfdf6e3)];
+static SOLARIZED_COOL: [RgbColor; 4] = [hex(0x2aa198), hex(0x268bd2), hex(0x6c71c4), hex(0x859900)];
+static SOLARIZED_WARM: [RgbColor; 4] = [hex(0xdc322f), hex(0xcb4b16), hex(0xb58900), hex(0xd33682)];
+// the light mode swaps the bases, its accents are the same
+static SOLARIZED_LIGHT_BACKGROUND: [RgbColor; 3] = [hex(0x93a1a1), hex(0xeee8d5), hex(0xfdf6e3)];
+static SOLARIZED_LIGHT_FOREGROUND: [RgbColor; 3] = [hex(0x073642), hex(0x586e75), hex(0x657b83)];
+
+// https://github.com/catppuccin/palette, the cool colors are teal to lavender, the rest is warm
+static MOCHA_BACKGROUND: [RgbColor; 6] = [hex(0x11111b), hex(0x181825), hex(0x1e1e2e), hex(0x313244), hex(0x45475a), hex(0x585b70)];
+static MOCHA_FOREGROUND: [RgbColor; 4] = [hex(0x9399b2), hex(0xa6adc8), hex(0xbac2de), hex(0xcdd6f4)];
+static MOCHA_COOL: [RgbColor; 5] = [hex(0x94e2d5), hex(0x89dceb), hex(0x74c7ec), hex(0x89b4fa), hex(0xb4befe)];
+static MOCHA_WARM: [RgbColor; 9] = [
+    hex(0xf5e0dc), hex(0xf2cdcd), hex(0xf5c2e7), hex(0xcba6f7), hex(0xf38ba8),
+    hex(0xeba0ac), hex(0xfab387), hex(0xf9e2af), hex(0xa6e3a1),
+];
+
+static MACCHIATO_BACKGROUND: [RgbColor; 6] = [hex(0x181926), hex(0x1e2030), hex(0x24273a), hex(0x363a4f), hex(0x494d64), hex(0x5b6078)];
+static MACCHIATO_FOREGROUND: [RgbColor; 4] = [hex(0x939ab7), hex(0xa5adcb), hex(0xb8c0e0), hex(0xcad3f5)];
+static MACCHIATO_COOL: [RgbColor; 5] = [hex(0x8bd5ca), hex(0x91d7e3), hex(0x7dc4e4), hex(0x8aadf4), hex(0xb7bdf8)];
+static MACCHIATO_WARM: [RgbColor; 9] = [
+    hex(0xf4dbd6), hex(0xf0c6c6), hex(0xf5bde6), hex(0xc6a0f6), hex(0xed8796),
+    hex(0xee99a0), hex(0xf5a97f), hex(0xeed49f), hex(0xa6da95),
+];
+
+static FRAPPE_BACKGROUND: [RgbColor; 6] = [hex(0x232634), hex(0x292c3c), hex(0x303446), hex(0x414559), hex(0x51576d), hex(0x626880)];
+static FRAPPE_FOREGROUND: [RgbColor; 4] = [hex(0x949cbb), hex(0xa5adce), hex(0xb5bfe2), hex(0xc6d0f5)];
+static FRAPPE_COOL: [RgbColor; 5] = [hex(0x81c8be), hex(0x99d1db), hex(0x85c1dc), hex(0x8caaee), hex(0xbabbf1)];
+static FRAPPE_WARM: [RgbColor; 9] = [
+    hex(0xf2d5cf), hex(0xeebebe), hex(0xf4b8e4), hex(0xca9ee6), hex(0xe78284),
+    hex(0xea999c), hex(0xef9f76), hex(0xe5c890), hex(0xa6d189),
+];
+
+// latte is light, its backgrounds are the bright surfaces and its foregrounds the dark text colors
+static LATTE_BACKGROUND: [RgbColor; 6] = [hex(0xacb0be), hex(0xbcc0cc), hex(0xccd0da), hex(0xdce0e8), hex(0xe6e9ef), hex(0xeff1f5)];
+static LATTE_FOREGROUND: [RgbColor; 4] = [hex(0x4c4f69), hex(0x5c5f77), hex(0x6c6f85), hex(0x7c7f93)];
+static LATTE_COOL: [RgbColor; 5] = [hex(0x179299), hex(0x04a5e5), hex(0x209fb5), hex(0x1e66f5), hex(0x7287fd)];
+static LATTE_WARM: [RgbColor; 9] = [
+    hex(0xdc8a78), hex(0xdd7878), hex(0xea76cb), hex(0x8839ef), hex(0xd20f39),
+    hex(0xe64553), hex(0xfe640b), hex(0xdf8e1d), hex(0x40a02b),
+];
+
+
+/// converts the image with the palette and filters of the options
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage, Rgba};
+/// use image2nord::colors::{apply_nord, calculate_average_brightness, NordOptions, Palette};
+///
+/// let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 2, Rgba([255, 255, 255, 255])));
+/// let information = calculate_average_brightness(&image.to_rgba8());
+/// let options = NordOptions { palette: Palette::Gruvbox, ..NordOptions::new() };
+/// let converted = apply_nord(image, options, &information);
+/// assert_eq!((converted.width(), converted.height()), (4, 2));
+/// assert!(calculate_average_brightness(&converted.to_rgba8()).brightness.average < 0.5);
+/// ```
+pub fn apply_nord(image: DynamicImage, options: NordOptions, info: &ImageInformation) -> DynamicImage {
+    let colors = PaletteColors::from_palette(options.palette);
+    apply_nord_with_colors(image, options, info, &colors)
+}
 
-struct Frost {}
-// impl for #8fbcbb #88c0d0 #81a1c1 #5e81ac
-impl Frost {
-    const A: RgbColor = RgbColor {r: 143, g: 188, b: 187};
-    const B: RgbColor = RgbColor {r: 136, g: 192, b: 208};
-    const C: RgbColor = RgbColor {r: 129, g: 161, b: 193};
-    const D: RgbColor = RgbColor {r: 94, g: 129, b: 172};
+/// apply_nord with Floyd-Steinberg dithering, which keeps gradients like skies free of banding
+pub fn apply_nord_dithered(image: DynamicImage, options: NordOptions, info: &ImageInformation) -> DynamicImage {
+    apply_nord(image, NordOptions { nord: true, dither: Dither::FloydSteinberg, ..options }, info)
 }
 
+/// apply_nord which keeps the brightness of every pixel and only takes over the hue of the palette,
+/// photos stay recognizable and grey images only get a slight cool tint
+pub fn apply_nord_tinted(image: DynamicImage, options: NordOptions, info: &ImageInformation) -> DynamicImage {
+    apply_nord(image, NordOptions { nord: true, preserve_luminance: true, ..options }, info)
+}
 
-pub fn apply_nord(mut _image: DynamicImage, options: NordOptions, info: &ImageInformation) -> DynamicImage {
+/// same as apply_nord, but matches against the given colors instead of the palette of the options
+pub fn apply_nord_with_colors(mut _image: DynamicImage, options: NordOptions, info: &ImageInformation, colors: &PaletteColors) -> DynamicImage {
     let mut image = _image.clone();
-    println!("{:?}", image.dimensions());
     //image = image.grayscale();
-    println!("Brightness of image is: {:.3}", info.brightness.average);
+    debug!("Applying palette width={} height={} brightness={:.3}", image.width(), image.height(), info.brightness.average);
 
     if options.erase_most_present_color {
         if options.model != Models::Algorithm {
@@ -549,7 +1422,7 @@ pub fn apply_nord(mut _image: DynamicImage, options: NordOptions, info: &ImageIn
             
             let start = std::time::Instant::now();
             let segmented_image = remove_background(session, image, &options);
-            println!("[Total] Time taken: {:.3} seconds", start.elapsed().as_secs_f32());
+            debug!("Removed background elapsed_ms={}", start.elapsed().as_millis());
             image = segmented_image;
         } else {
             //Remove most present color if above threshold
@@ -578,36 +1451,291 @@ pub fn apply_nord(mut _image: DynamicImage, options: NordOptions, info: &ImageIn
             .huerotate(options.hue_rotate as i32)
             .to_rgba8();
     }
-    if options.nord {
-        apply_nord_filter(&mut mod_image, &options);
+    let max_brightness = if options.erase_most_present_color || options.palette.is_light() {1.} else {0.85};
+    if options.nord && options.snap {
+        snap_to_palette(&mut mod_image, colors, options.metric, options.dither, max_brightness, options.grayscale);
+    } else if options.nord && options.gradient_map {
+        let ramp = gradient_ramp(options.palette, &options.gradient_ramp, colors);
+        gradient_map(&mut mod_image, &ramp);
+    } else if options.nord {
+        apply_palette_filter(
+            &mut mod_image, colors, options.metric, options.dither, 
+            max_brightness, options.preserve_luminance, options.grayscale
+        );
     }
-    if options.sepia || options.hue_rotate != 0.0 || options.nord {
+    let converted = if options.sepia || options.hue_rotate != 0.0 || options.nord {
         DynamicImage::from(mod_image)
     } else {
         image
+    };
+    // snapped images have to stay on the palette, any of the original would bring other colors back
+    if options.snap {
+        return converted;
     }
-    
+    let converted = if options.bright_only {
+        blend_bright_regions(&_image, converted, BRIGHT_ONLY_THRESHOLD, BRIGHT_ONLY_FEATHER)
+    } else {
+        converted
+    };
+    blend_images(&_image, converted, options.strength)
 }
 
+/// brightness above which the bright only mode converts pixels
+pub const BRIGHT_ONLY_THRESHOLD: f32 = 0.6;
+/// sigma of the blur which softens the edge between converted and untouched areas
+pub const BRIGHT_ONLY_FEATHER: f32 = 3.0;
+
+/// takes the converted pixels where the original is brighter than `threshold` and keeps the original elsewhere,
+/// the mask is blurred by `feather` so the boundary doesn't show a seam
+pub fn blend_bright_regions(original: &DynamicImage, converted: DynamicImage, threshold: f32, feather: f32) -> DynamicImage {
+    let original = original.to_rgba8();
+    let mut converted = converted.to_rgba8();
+    if original.dimensions() != converted.dimensions() {
+        return DynamicImage::ImageRgba8(converted);
+    }
+    let mask = GrayImage::from_fn(original.width(), original.height(), |x, y| {
+        let Rgba([r, g, b, _]) = *original.get_pixel(x, y);
+        Luma([if (RgbColor { r, g, b }).brightness() > threshold { 255 } else { 0 }])
+    });
+    let mask = if feather > 0.0 { gaussian_blur_f32(&mask, feather) } else { mask };
+    for ((converted_pixel, original_pixel), Luma([weight])) in converted.pixels_mut().zip(original.pixels()).zip(mask.pixels()) {
+        let weight = *weight as f32 / 255.0;
+        for c in 0..3 {
+            let mixed = original_pixel[c] as f32 * (1.0 - weight) + converted_pixel[c] as f32 * weight;
+            converted_pixel[c] = mixed.round() as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(converted)
+}
 
-
-pub fn _tint_image(image: &mut RgbaImage, tint: Rgb<f32>) {
-    let Rgb([tint_r, tint_g, tint_b]) = tint;
-    for Rgba([r, g, b, _]) in image.pixels_mut() {
-        *r = (*r as f32 * tint_r) as u8;
-        *g = (*g as f32 * tint_g) as u8;
-        *b = (*b as f32 * tint_b) as u8;
+/// mixes the converted image into the original, 0.0 keeps the original and 1.0 the converted image
+pub fn blend_images(original: &DynamicImage, converted: DynamicImage, strength: f32) -> DynamicImage {
+    if strength >= 1.0 {
+        return converted;
+    }
+    if strength <= 0.0 {
+        return original.clone();
     }
+    let original = original.to_rgba8();
+    let mut converted = converted.to_rgba8();
+    if original.dimensions() != converted.dimensions() {
+        return DynamicImage::ImageRgba8(converted);
+    }
+    let row_len = original.width() as usize * 4;
+    for_each_row_band(&mut converted, |first_y, subpixels| {
+        let start = first_y as usize * row_len;
+        let original = &original.as_raw()[start..start + subpixels.len()];
+        for (converted, original) in subpixels.iter_mut().zip(original) {
+            *converted = (*original as f32 * (1.0 - strength) + *converted as f32 * strength).round() as u8;
+        }
+    });
+    DynamicImage::ImageRgba8(converted)
 }
 
-pub fn apply_sepia(image: &mut RgbaImage) {
-    for Rgba([r, g, b, _]) in image.pixels_mut() {
-        let tr = (0.393 * *r as f32 + 0.769 * *g as f32 + 0.189 * *b as f32).min(255.0) as u8;
-        let tg = (0.349 * *r as f32 + 0.686 * *g as f32 + 0.168 * *b as f32).min(255.0) as u8;
-        let tb = (0.272 * *r as f32 + 0.534 * *g as f32 + 0.131 * *b as f32).min(255.0) as u8;
-        *r = tr;
-        *g = tg;
-        *b = tb;
+
+/// downscales images with a side longer than max_dim, keeping the aspect ratio
+pub fn fit_within(image: DynamicImage, max_dim: u32) -> DynamicImage {
+    if image.width() <= max_dim && image.height() <= max_dim {
+        return image;
+    }
+    debug!("Downscaling width={} height={} max_dimension={max_dim}", image.width(), image.height());
+    image.resize(max_dim, max_dim, FilterType::Lanczos3)
+}
+
+/// resizes the converted image, nearest neighbor keeps the palette colors clean when upscaling
+pub fn scale_output(image: DynamicImage, scale: f32, max_dim: u32) -> DynamicImage {
+    let scale = scale.clamp(0.25, 2.0);
+    let longest_side = image.width().max(image.height()).max(1);
+    // never go past the limit used for inputs, but don't shrink images which are already above it
+    let scale = scale.min((max_dim as f32 / longest_side as f32).max(1.0));
+    if (scale - 1.0).abs() < f32::EPSILON {
+        return image;
+    }
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+    let filter = if scale > 1.0 { FilterType::Nearest } else { FilterType::Lanczos3 };
+    image.resize_exact(width, height, filter)
+}
+
+/// mean and max distance of the visible pixels to the palette color they would be mapped to,
+/// so 0.0 means the palette already has every color of the image.
+/// The custom palette falls back to Nord, like in PaletteColors::from_palette
+pub fn analyze_conversion(image: &DynamicImage, palette: Palette, metric: DistanceMetric) -> (f64, f64) {
+    let palette = if palette == Palette::Custom { Palette::Nord } else { palette };
+    let colors = palette.colors();
+    let mut distances: HashMap<(u8, u8, u8), f64> = HashMap::new();
+    let (mut total, mut max, mut count) = (0.0, 0.0f64, 0u64);
+    for Rgba([r, g, b, a]) in image.to_rgba8().pixels() {
+        if *a == 0 {
+            continue;
+        }
+        let distance = *distances.entry((*r, *g, *b)).or_insert_with(|| {
+            let color = RgbColor { r: *r, g: *g, b: *b };
+            metric.distance(&color, get_nearest_color(&color, &colors, metric)) as f64
+        });
+        total += distance;
+        max = max.max(distance);
+        count += 1;
+    }
+    if count == 0 { (0.0, 0.0) } else { (total / count as f64, max) }
+}
+
+/// repeats the image without scaling until it fills the target size, the last tiles get cut off
+pub fn tile_to(image: &DynamicImage, target_w: u32, target_h: u32) -> DynamicImage {
+    let source = image.to_rgba8();
+    if source.width() == 0 || source.height() == 0 {
+        return DynamicImage::ImageRgba8(RgbaImage::new(target_w, target_h));
+    }
+    let tiled = RgbaImage::from_fn(target_w, target_h, |x, y| {
+        *source.get_pixel(x % source.width(), y % source.height())
+    });
+    DynamicImage::ImageRgba8(tiled)
+}
+
+/// pushes every channel away from the brightness of the pixel, 1.0 keeps the image as it is.
+/// Washed out photos then spread over more palette colors
+pub fn boost_saturation(image: DynamicImage, amount: f32) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    if (amount - 1.0).abs() < f32::EPSILON {
+        return DynamicImage::ImageRgba8(image);
+    }
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        let brightness = RgbColor { r: *r, g: *g, b: *b }.brightness() * 255.0;
+        for channel in [r, g, b] {
+            *channel = (brightness + (*channel as f32 - brightness) * amount).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// fewest and most levels per channel for posterize, 256 levels keep the image as it is
+pub const MIN_POSTERIZE_LEVELS: u16 = 2;
+pub const MAX_POSTERIZE_LEVELS: u16 = 8;
+
+/// rounds every channel to one of `levels` evenly spaced values, for flat shading before the palette mapping.
+/// Levels below MIN_POSTERIZE_LEVELS are raised to it, 256 and more change nothing
+pub fn posterize(image: DynamicImage, levels: u16) -> DynamicImage {
+    if levels >= 256 {
+        return image;
+    }
+    let steps = (levels.max(MIN_POSTERIZE_LEVELS) - 1) as f32;
+    let mut image = image.to_rgba8();
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        for channel in [r, g, b] {
+            *channel = ((*channel as f32 / 255.0 * steps).round() / steps * 255.0).round() as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// radius of the smoothing window when noise smoothing is on, 1 keeps 1px text readable
+pub const SMOOTH_RADIUS: u32 = 1;
+/// how different in color a neighbour may be and still count, edges of text are far above this
+const SMOOTH_COLOR_SIGMA: f32 = 24.0;
+
+/// edge preserving blur against sensor noise, a small bilateral filter:
+/// neighbours are averaged in, weighted by their distance and by how close their color is,
+/// so noise on flat areas goes away while the contrast of edges and thin lines stays
+pub fn smooth_noise(image: DynamicImage, radius: u32) -> DynamicImage {
+    if radius == 0 {
+        return image;
+    }
+    let source = image.to_rgba8();
+    let mut smoothed = source.clone();
+    let (width, height) = source.dimensions();
+    let radius = radius as i64;
+    let spatial_sigma = radius as f32;
+    for_each_row_band(&mut smoothed, |first_y, subpixels| {
+        for (i, pixel) in subpixels.chunks_exact_mut(4).enumerate() {
+            let x = (i % width as usize) as i64;
+            let y = first_y as i64 + (i / width as usize) as i64;
+            let center = source.get_pixel(x as u32, y as u32);
+            let (mut sum, mut total) = ([0.0f32; 3], 0.0f32);
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let neighbour = source.get_pixel(nx as u32, ny as u32);
+                    let color_distance: f32 = (0..3)
+                        .map(|c| (neighbour[c] as f32 - center[c] as f32).powi(2))
+                        .sum();
+                    let weight = (-((dx * dx + dy * dy) as f32) / (2.0 * spatial_sigma.powi(2))
+                        - color_distance / (2.0 * SMOOTH_COLOR_SIGMA.powi(2))).exp();
+                    for c in 0..3 {
+                        sum[c] += neighbour[c] as f32 * weight;
+                    }
+                    total += weight;
+                }
+            }
+            for c in 0..3 {
+                pixel[c] = (sum[c] / total).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    });
+    DynamicImage::ImageRgba8(smoothed)
+}
+
+/// stretches the histogram so the darkest low_pct percent become black and the lightest high_pct percent white
+pub fn auto_contrast(image: DynamicImage, low_pct: f32, high_pct: f32) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    let mut histogram = [0u64; 256];
+    let mut visible = 0u64;
+    for Rgba([r, g, b, a]) in image.pixels() {
+        if *a == 0 {
+            continue;
+        }
+        let brightness = RgbColor { r: *r, g: *g, b: *b }.brightness();
+        histogram[(brightness * 255.0).round() as usize] += 1;
+        visible += 1;
+    }
+    if visible == 0 {
+        return DynamicImage::ImageRgba8(image);
+    }
+    let percentile = |pct: f32| {
+        let wanted = (visible as f32 * pct.clamp(0.0, 100.0) / 100.0) as u64;
+        let mut count = 0;
+        for (value, amount) in histogram.iter().enumerate() {
+            count += amount;
+            if count > wanted {
+                return value as f32;
+            }
+        }
+        255.0
+    };
+    let low = percentile(low_pct);
+    let high = percentile(100.0 - high_pct);
+    if high - low < 1.0 {
+        // a single color, nothing to stretch
+        return DynamicImage::ImageRgba8(image);
+    }
+    let stretch = |channel: u8| ((channel as f32 - low) * 255.0 / (high - low)).round().clamp(0.0, 255.0) as u8;
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        *r = stretch(*r);
+        *g = stretch(*g);
+        *b = stretch(*b);
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+pub fn _tint_image(image: &mut RgbaImage, tint: Rgb<f32>) {
+    let Rgb([tint_r, tint_g, tint_b]) = tint;
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        *r = (*r as f32 * tint_r) as u8;
+        *g = (*g as f32 * tint_g) as u8;
+        *b = (*b as f32 * tint_b) as u8;
+    }
+}
+
+pub fn apply_sepia(image: &mut RgbaImage) {
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        let tr = (0.393 * *r as f32 + 0.769 * *g as f32 + 0.189 * *b as f32).min(255.0) as u8;
+        let tg = (0.349 * *r as f32 + 0.686 * *g as f32 + 0.168 * *b as f32).min(255.0) as u8;
+        let tb = (0.272 * *r as f32 + 0.534 * *g as f32 + 0.131 * *b as f32).min(255.0) as u8;
+        *r = tr;
+        *g = tg;
+        *b = tb;
     }
 }
 
@@ -626,96 +1754,652 @@ pub fn _apply_tone(image: &mut RgbaImage, target_color: Rgb<f32>, blend_factor:
 
 
 
+/// true if nearly every visible pixel already is a color of the palette, like images the bot converted
+pub fn is_already_converted(image: &RgbaImage, palette: Palette) -> bool {
+    const SAMPLE_DISTANCE: usize = 50;
+    const MAX_DISTANCE: f32 = 12.0;
+    const MIN_FRACTION: f32 = 0.9;
+    let colors = palette.colors();
+    if colors.is_empty() {
+        return false;
+    }
+    let mut sampled = 0;
+    let mut in_palette = 0;
+    for (i, Rgba([r, g, b, a])) in image.pixels().enumerate() {
+        if i % SAMPLE_DISTANCE != 0 || *a <= VISIBLE_ALPHA {
+            continue;
+        }
+        sampled += 1;
+        let pixel = RgbColor { r: *r, g: *g, b: *b };
+        let nearest = get_nearest_color(&pixel, &colors, DistanceMetric::RgbEuclidean);
+        if DistanceMetric::RgbEuclidean.distance(&pixel, nearest) <= MAX_DISTANCE {
+            in_palette += 1;
+        }
+    }
+    sampled > 0 && in_palette as f32 / sampled as f32 >= MIN_FRACTION
+}
+
+/// brightness, grayness and the most present color of the image,
+/// the brightness is the perceived lightness, measured on a copy of at most 256 pixels per side
+///
+/// ```
+/// use image::{RgbaImage, Rgba};
+///
+/// let white = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+/// let information = image2nord::colors::calculate_average_brightness(&white);
+/// assert!(information.brightness.average > 0.9);
+/// ```
 pub fn calculate_average_brightness(image: &RgbaImage) -> ImageInformation {
-    let image_information = get_image_information(&image);
-    println!("--------------- IMAGE INFORMATION -------------\n{:?}", image_information);
+    let image_information = get_image_information(&downsample_for_analysis(image));
+    debug!("Image information {:?}", image_information);
     image_information
 }
 
-pub fn apply_nord_filter(image: &mut RgbaImage, options: &NordOptions) {
-    let mut smallest_grey = f32::MAX;
-    let mut biggest_grey = f32::MIN;
-    let max_brightness = if options.erase_most_present_color {1.} else {0.85};
+/// scales all channels down until the average brightness is at most `target` (0.0 - 1.0), 
+/// the hue and saturation stay as they are
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage, Rgba};
+/// use image2nord::colors::{calculate_average_brightness, dim_to_brightness};
+///
+/// let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(60, 60, Rgba([250, 240, 200, 255])));
+/// let dimmed = dim_to_brightness(image, 0.4);
+/// assert!(calculate_average_brightness(&dimmed.to_rgba8()).brightness.average <= 0.4);
+/// ```
+pub fn dim_to_brightness(image: DynamicImage, target: f32) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    let sample = downsample_for_analysis(&image);
+    let average = get_image_information(&sample).brightness.average;
+    if average <= target || average <= 0.0 {
+        return DynamicImage::ImageRgba8(image);
+    }
+    let scaled = |factor: f32| {
+        let mut scaled = sample.clone();
+        for Rgba([r, g, b, _]) in scaled.pixels_mut() {
+            for channel in [r, g, b] {
+                *channel = (*channel as f32 * factor) as u8;
+            }
+        }
+        scaled
+    };
+    // lightness isn't linear in the channels, so the factor is searched on the small copy,
+    // keeping the lower bound keeps it below the target
+    let (mut factor, mut too_bright) = (0.0f32, 1.0f32);
+    for _ in 0..12 {
+        let middle = (factor + too_bright) / 2.0;
+        if get_image_information(&scaled(middle)).brightness.average <= target {
+            factor = middle;
+        } else {
+            too_bright = middle;
+        }
+    }
+    for Rgba([r, g, b, _]) in image.pixels_mut() {
+        for channel in [r, g, b] {
+            *channel = (*channel as f32 * factor) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+/// maps the color of every pixel onto the given colors, the alpha channel is kept as is
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage, Rgba};
+/// use image2nord::colors::{apply_palette, DistanceMetric, Dither};
+///
+/// let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255])));
+/// let converted = apply_palette(image, &[[46, 52, 64], [236, 239, 244]], DistanceMetric::RgbEuclidean, Dither::FloydSteinberg, false, false);
+/// let pixel = converted.to_rgba8().get_pixel(0, 0).0;
+/// assert!(pixel == [46, 52, 64, 255] || pixel == [236, 239, 244, 255]);
+/// ```
+pub fn apply_palette(
+    image: DynamicImage, 
+    colors: &[[u8; 3]], 
+    metric: DistanceMetric, 
+    dither: Dither, 
+    preserve_luminance: bool,
+    grayscale: bool
+) -> DynamicImage {
+    let mut image = image.to_rgba8();
+    apply_palette_filter(&mut image, &PaletteColors::from_rgb(colors), metric, dither, 0.85, preserve_luminance, grayscale);
+    DynamicImage::from(image)
+}
 
-    let contrast_colors = vec![
-        PolarNight::A, PolarNight::B, PolarNight::C, PolarNight::D,
-    ];
+/// same as apply_palette, but also tells how the result looks
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage, Rgba};
+/// use image2nord::colors::{apply_palette_with_stats, DistanceMetric, Dither};
+///
+/// let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, _| Rgba([x as u8 * 30, 0, 0, 255])));
+/// let (_, stats) = apply_palette_with_stats(image, &[[46, 52, 64], [191, 97, 106]], DistanceMetric::RgbEuclidean, Dither::None, false, false);
+/// assert_eq!(stats.pixels, 64);
+/// assert!(stats.colors_used >= 1);
+/// ```
+pub fn apply_palette_with_stats(
+    image: DynamicImage, 
+    colors: &[[u8; 3]], 
+    metric: DistanceMetric, 
+    dither: Dither, 
+    preserve_luminance: bool,
+    grayscale: bool
+) -> (DynamicImage, ConversionStats) {
+    let image = apply_palette(image, colors, metric, dither, preserve_luminance, grayscale);
+    let stats = ConversionStats::from_image(&image.to_rgba8());
+    (image, stats)
+}
 
-    let colorful_colors = vec![
-        Frost::A, Frost::B, Frost::C, Frost::D,
-    ];
+#[derive(Clone, Debug)]
+pub struct ConversionStats {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: u64,
+    /// distinct colors of the visible pixels
+    pub colors_used: usize,
+    /// how many visible pixels have each color
+    pub color_counts: HashMap<(u8, u8, u8), u64>,
+}
 
-    for color in &contrast_colors {
-        println!("{} {} {} has brightness {:.3}", color.r, color.g, color.b, color.brightness());
+impl ConversionStats {
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let mut color_counts = HashMap::new();
+        for Rgba([r, g, b, a]) in image.pixels() {
+            if *a > 0 {
+                *color_counts.entry((*r, *g, *b)).or_insert(0) += 1;
+            }
+        }
+        ConversionStats {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.width() as u64 * image.height() as u64,
+            colors_used: color_counts.len(),
+            color_counts,
+        }
     }
 
-    fn get_nearest_color<'a>(color: &RgbColor, all_colors: &'a [RgbColor]) -> &'a RgbColor {
-        let mut min_distance = f32::MAX;
-        let mut nearest_color = &all_colors[0];
-        let br = color.brightness();
-        for c in all_colors.iter() {
-            let dist = (c.brightness() - br).abs();
-            if dist < min_distance {
-                min_distance = dist;
-                nearest_color = c;
-            }
+    /// share of the visible pixels (0.0 - 1.0) per palette color, 
+    /// colors which got blended with the original count for the closest swatch
+    pub fn palette_usage(&self, palette: &[RgbColor]) -> Vec<f32> {
+        if palette.is_empty() {
+            return Vec::new();
+        }
+        let mut usage = vec![0u64; palette.len()];
+        for (&(r, g, b), count) in &self.color_counts {
+            let nearest = get_nearest_color(&RgbColor { r, g, b }, palette, DistanceMetric::RgbEuclidean);
+            let i = palette.iter().position(|color| std::ptr::eq(color, nearest)).unwrap_or(0);
+            usage[i] += count;
         }
-        nearest_color
+        let visible = usage.iter().sum::<u64>().max(1) as f32;
+        usage.into_iter().map(|count| count as f32 / visible).collect()
     }
+}
 
-    let mut cache: HashMap<(u8, u8, u8), (u8, u8, u8)> = HashMap::new();
+pub fn apply_palette_filter(
+    image: &mut RgbaImage, 
+    colors: &PaletteColors, 
+    metric: DistanceMetric, 
+    dither: Dither, 
+    max_brightness: f32,
+    preserve_luminance: bool,
+    grayscale: bool
+) {
+    let original = preserve_luminance.then(|| image.clone());
+    match dither {
+        _ if grayscale => map_to_neutral_ramp(image, colors, max_brightness),
+        Dither::None => blend_with_palette(image, colors, metric, max_brightness),
+        Dither::Ordered(size, strength) => ordered_dither(image, colors, metric, size, strength, max_brightness),
+        Dither::FloydSteinberg => floyd_steinberg_dither(image, colors, metric, max_brightness),
+        Dither::TwoColor => two_color_blend(image, colors, metric, max_brightness),
+    }
+    if let Some(original) = original {
+        match_luminance(image, &original, max_brightness);
+    }
+}
 
-    for Rgba([r, g, b, _]) in image.pixels_mut() {
-        let key = (*r, *g, *b);
-        if let Some(&(cached_r, cached_g, cached_b)) = cache.get(&key) {
-            *r = cached_r;
-            *g = cached_g;
-            *b = cached_b;
+/// like apply_palette_filter, but every visible pixel ends up exactly on one of the colors:
+/// the modes which mix colors map to the nearest color instead, the dithering modes only pick palette colors anyway
+pub fn snap_to_palette(
+    image: &mut RgbaImage, 
+    colors: &PaletteColors, 
+    metric: DistanceMetric, 
+    dither: Dither, 
+    max_brightness: f32,
+    grayscale: bool
+) {
+    match dither {
+        _ if grayscale => map_to_neutral_ramp(image, colors, max_brightness),
+        Dither::Ordered(size, strength) => ordered_dither(image, colors, metric, size, strength, max_brightness),
+        Dither::FloydSteinberg => floyd_steinberg_dither(image, colors, metric, max_brightness),
+        Dither::None | Dither::TwoColor => map_to_nearest(image, colors, metric, max_brightness),
+    }
+}
+
+/// replaces every visible pixel with its nearest palette color
+fn map_to_nearest(image: &mut RgbaImage, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) {
+    let lut = NearestColorLut::for_image(image, colors, metric, max_brightness);
+    for_each_row_band(image, |_, pixels| {
+        let mut cache: HashMap<(u8, u8, u8), (u8, u8, u8)> = HashMap::new();
+        for pixel in pixels.chunks_exact_mut(4) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let key = (pixel[0], pixel[1], pixel[2]);
+            let (r, g, b) = *cache.entry(key).or_insert_with(|| {
+                let color = RgbColor { r: key.0, g: key.1, b: key.2 };
+                let (_, nearest) = lookup_nearest(color, lut.as_ref(), colors, metric, max_brightness);
+                (nearest.r, nearest.g, nearest.b)
+            });
+            pixel[0] = r;
+            pixel[1] = g;
+            pixel[2] = b;
+        }
+    });
+}
+
+/// replaces every pixel with the neutral color closest to its brightness, so the hue doesn't matter
+fn map_to_neutral_ramp(image: &mut RgbaImage, colors: &PaletteColors, max_brightness: f32) {
+    for Rgba([r, g, b, a]) in image.pixels_mut() {
+        if *a == 0 {
             continue;
         }
+        let brightness = RgbColor { r: *r, g: *g, b: *b }.brightness().min(max_brightness);
+        let nearest_color = colors.neutrals
+            .iter()
+            .min_by(|c1, c2| {
+                let d1 = (c1.brightness() - brightness).abs();
+                let d2 = (c2.brightness() - brightness).abs();
+                d1.total_cmp(&d2)
+            })
+            .expect("palettes always have neutral colors");
+        *r = nearest_color.r;
+        *g = nearest_color.g;
+        *b = nearest_color.b;
+    }
+}
 
-        let color = RgbColor { r: *r, g: *g, b: *b };
-        let current_pixel_br = color.brightness();
-        let grayscale_similarity = color.calculate_grayscale_similarity();
+/// the colors at `indices` of the palette, ordered dark to light.
+/// Custom palettes and ramps with less than two stops use the neutral colors instead
+pub fn gradient_ramp(palette: Palette, indices: &[usize], colors: &PaletteColors) -> Vec<RgbColor> {
+    let palette_colors = palette.colors();
+    // stops outside of the colors, like groups which were left out, are skipped
+    let available = |color: &RgbColor| {
+        [&colors.darks, &colors.accents, &colors.neutrals, &colors.colorful].iter().any(|list| list.contains(color))
+    };
+    let mut ramp: Vec<RgbColor> = indices
+        .iter()
+        .filter_map(|&i| palette_colors.get(i).cloned())
+        .filter(available)
+        .collect();
+    if ramp.len() < 2 {
+        ramp = colors.neutrals.clone();
+    }
+    ramp.sort_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness()));
+    ramp
+}
 
-        if grayscale_similarity < smallest_grey {
-            smallest_grey = grayscale_similarity;
+/// replaces every pixel with the color at its luminance on the ramp, 
+/// neighbouring stops are mixed in linear light so the midtones don't get muddy
+pub fn gradient_map(image: &mut RgbaImage, ramp: &[RgbColor]) {
+    let Some(last) = ramp.len().checked_sub(1) else {
+        return;
+    };
+    let linear: Vec<[f32; 3]> = ramp
+        .iter()
+        .map(|color| [srgb_to_linear(color.rn()), srgb_to_linear(color.gn()), srgb_to_linear(color.bn())])
+        .collect();
+    for_each_row_band(image, |_, pixels| {
+        for pixel in pixels.chunks_exact_mut(4) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let luminance = RgbColor { r: pixel[0], g: pixel[1], b: pixel[2] }.brightness().clamp(0.0, 1.0);
+            let position = luminance * last as f32;
+            let i = (position.floor() as usize).min(last.saturating_sub(1));
+            let j = (i + 1).min(last);
+            let t = (position - i as f32).clamp(0.0, 1.0);
+            for c in 0..3 {
+                let mixed = linear[i][c] * (1.0 - t) + linear[j][c] * t;
+                pixel[c] = (linear_to_srgb(mixed) * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
         }
-        if grayscale_similarity > biggest_grey {
-            biggest_grey = grayscale_similarity;
+    });
+}
+
+/// shifts every pixel so its brightness matches the original again, capped at max_brightness
+fn match_luminance(image: &mut RgbaImage, original: &RgbaImage, max_brightness: f32) {
+    if image.dimensions() != original.dimensions() {
+        return;
+    }
+    let row_len = original.width() as usize * 4;
+    for_each_row_band(image, |first_y, subpixels| {
+        let start = first_y as usize * row_len;
+        let original = &original.as_raw()[start..start + subpixels.len()];
+        for (pixel, original) in subpixels.chunks_exact_mut(4).zip(original.chunks_exact(4)) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let wanted = RgbColor { r: original[0], g: original[1], b: original[2] }.brightness().min(max_brightness);
+            let current = RgbColor { r: pixel[0], g: pixel[1], b: pixel[2] }.brightness();
+            // the brightness weights add up to 1, so shifting all channels equally moves the brightness by that much
+            let shift = (wanted - current) * 255.0;
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 + shift).round().clamp(0.0, 255.0) as u8;
+            }
         }
+    });
+}
 
-        let darken_by = (current_pixel_br - max_brightness).max(0.0);
-        let adjusted_color = if darken_by > 0.0 {
-            color.darken_rgb(darken_by)
-        } else {
-            color
-        };
+fn get_nearest_color<'a>(color: &RgbColor, all_colors: &'a [RgbColor], metric: DistanceMetric) -> &'a RgbColor {
+    if metric == DistanceMetric::Ciede2000 {
+        // converting to lab is the expensive part, the pixel only needs it once
+        let lab = color.to_lab();
+        return all_colors
+            .iter()
+            .min_by(|c1, c2| ciede2000(lab, palette_lab(c1)).total_cmp(&ciede2000(lab, palette_lab(c2))))
+            .unwrap_or(&all_colors[0]);
+    }
+    let mut min_distance = f32::MAX;
+    let mut nearest_color = &all_colors[0];
+    for c in all_colors.iter() {
+        let dist = metric.distance(color, c);
+        if dist < min_distance {
+            min_distance = dist;
+            nearest_color = c;
+        }
+    }
+    nearest_color
+}
 
-        let nearest_color = if grayscale_similarity < 0.25 {
-            get_nearest_color(&adjusted_color, &contrast_colors)
-        } else {
-            get_nearest_color(&adjusted_color, &colorful_colors)
-        };
+/// returns the darkened color and the palette color it should be moved to
+fn nearest_palette_color<'a>(
+    color: RgbColor, 
+    colors: &'a PaletteColors, 
+    metric: DistanceMetric, 
+    max_brightness: f32
+) -> (RgbColor, &'a RgbColor) {
+    let grayscale_similarity = color.calculate_grayscale_similarity();
+    let adjusted_color = darken_to(color, max_brightness);
+    let nearest_color = get_nearest_color(&adjusted_color, candidate_colors(grayscale_similarity, colors, metric), metric);
+    (adjusted_color, nearest_color)
+}
 
-        let strength = (1.0 - (current_pixel_br - nearest_color.brightness()).abs()) * 0.8;
+/// darkens colors brighter than max_brightness by the difference
+fn darken_to(color: RgbColor, max_brightness: f32) -> RgbColor {
+    let darken_by = (color.brightness() - max_brightness).max(0.0);
+    if darken_by > 0.0 {
+        color.darken_rgb(darken_by)
+    } else {
+        color
+    }
+}
 
-        let blended_r = (adjusted_color.rn() * (1.0 - strength) + nearest_color.rn() * strength) * 255.0;
-        let blended_g = (adjusted_color.gn() * (1.0 - strength) + nearest_color.gn() * strength) * 255.0;
-        let blended_b = (adjusted_color.bn() * (1.0 - strength) + nearest_color.bn() * strength) * 255.0;
+/// like nearest_palette_color, but from the lookup table when the image is big enough to have one
+fn lookup_nearest(
+    color: RgbColor,
+    lut: Option<&NearestColorLut>,
+    colors: &PaletteColors,
+    metric: DistanceMetric,
+    max_brightness: f32
+) -> (RgbColor, RgbColor) {
+    match lut {
+        Some(lut) => {
+            let (r, g, b) = lut.get(color.r, color.g, color.b);
+            (darken_to(color, max_brightness), RgbColor { r, g, b })
+        }
+        None => {
+            let (adjusted, nearest) = nearest_palette_color(color, colors, metric, max_brightness);
+            (adjusted, nearest.clone())
+        }
+    }
+}
 
-        let final_r = blended_r.min(255.0) as u8;
-        let final_g = blended_g.min(255.0) as u8;
-        let final_b = blended_b.min(255.0) as u8;
+/// greyish colors are matched against the darks, colorful ones against the accents
+fn candidate_colors(grayscale_similarity: f32, colors: &PaletteColors, metric: DistanceMetric) -> &[RgbColor] {
+    if grayscale_similarity < 0.25 {
+        &colors.darks
+    } else if metric == DistanceMetric::Hsl {
+        &colors.colorful
+    } else {
+        &colors.accents
+    }
+}
 
-        cache.insert(key, (final_r, final_g, final_b));
+/// mixes the two palette colors nearest to the darkened color, the closer one gets the bigger share
+fn two_color_pixel(color: RgbColor, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) -> (u8, u8, u8) {
+    let candidates = candidate_colors(color.calculate_grayscale_similarity(), colors, metric);
+    let darken_by = (color.brightness() - max_brightness).max(0.0);
+    let adjusted_color = if darken_by > 0.0 { color.darken_rgb(darken_by) } else { color };
+    let mut by_distance: Vec<(f32, &RgbColor)> = candidates
+        .iter()
+        .map(|candidate| (metric.distance(&adjusted_color, candidate), candidate))
+        .collect();
+    by_distance.sort_by(|(d1, _), (d2, _)| d1.total_cmp(d2));
+    let (nearest, second) = match by_distance[..] {
+        [(d1, c1), (d2, c2), ..] if d1 + d2 > 0.0 => ((d1, c1), (d2, c2)),
+        [(_, c1), ..] => return (c1.r, c1.g, c1.b),
+        [] => return (color.r, color.g, color.b),
+    };
+    // inverse distance weights, d2 / (d1 + d2) is the share of the nearest color
+    let weight = second.0 / (nearest.0 + second.0);
+    let mix = |c1: u8, c2: u8| (c1 as f32 * weight + c2 as f32 * (1.0 - weight)).round() as u8;
+    (mix(nearest.1.r, second.1.r), mix(nearest.1.g, second.1.g), mix(nearest.1.b, second.1.b))
+}
+
+fn two_color_blend(image: &mut RgbaImage, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) {
+    for_each_row_band(image, |_, pixels| {
+        let mut cache: HashMap<(u8, u8, u8), (u8, u8, u8)> = HashMap::new();
+        for pixel in pixels.chunks_exact_mut(4) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let key = (pixel[0], pixel[1], pixel[2]);
+            let (r, g, b) = *cache
+                .entry(key)
+                .or_insert_with(|| two_color_pixel(RgbColor { r: key.0, g: key.1, b: key.2 }, colors, metric, max_brightness));
+            pixel[0] = r;
+            pixel[1] = g;
+            pixel[2] = b;
+        }
+    });
+}
 
-        *r = final_r;
-        *g = final_g;
-        *b = final_b;
+fn blend_with_palette(image: &mut RgbaImage, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) {
+    let lut = NearestColorLut::for_image(image, colors, metric, max_brightness);
+    for_each_row_band(image, |_, pixels| {
+        // every band has its own cache, so the threads don't wait for each other
+        let mut cache: HashMap<(u8, u8, u8), (u8, u8, u8)> = HashMap::new();
+        for pixel in pixels.chunks_exact_mut(4) {
+            // invisible anyway, mapping them would only bleed into the edges when scaled
+            if pixel[3] == 0 {
+                continue;
+            }
+            let key = (pixel[0], pixel[1], pixel[2]);
+            let (r, g, b) = *cache
+                .entry(key)
+                .or_insert_with(|| blend_pixel(RgbColor { r: key.0, g: key.1, b: key.2 }, lut.as_ref(), colors, metric, max_brightness));
+            pixel[0] = r;
+            pixel[1] = g;
+            pixel[2] = b;
+        }
+    });
+}
+
+/// moves the color towards the nearest palette color, the closer their brightness the further
+fn blend_pixel(
+    color: RgbColor,
+    lut: Option<&NearestColorLut>,
+    colors: &PaletteColors,
+    metric: DistanceMetric,
+    max_brightness: f32
+) -> (u8, u8, u8) {
+    let current_pixel_br = color.brightness();
+    let (adjusted_color, nearest_color) = lookup_nearest(color, lut, colors, metric, max_brightness);
+
+    let strength = (1.0 - (current_pixel_br - nearest_color.brightness()).abs()) * 0.8;
+
+    let blended_r = (adjusted_color.rn() * (1.0 - strength) + nearest_color.rn() * strength) * 255.0;
+    let blended_g = (adjusted_color.gn() * (1.0 - strength) + nearest_color.gn() * strength) * 255.0;
+    let blended_b = (adjusted_color.bn() * (1.0 - strength) + nearest_color.bn() * strength) * 255.0;
+
+    (blended_r.min(255.0) as u8, blended_g.min(255.0) as u8, blended_b.min(255.0) as u8)
+}
+
+/// images with fewer pixels are mapped on one thread, splitting them costs more than it saves
+const PARALLEL_MIN_PIXELS: usize = 256 * 256;
+const ROWS_PER_BAND: usize = 16;
+
+/// calls `map_rows` with the y of the first row and the subpixels of every band of rows,
+/// on all cores for big images. Pixels must be mapped independently of each other,
+/// then the result is the same for any number of threads
+fn for_each_row_band(image: &mut RgbaImage, map_rows: impl Fn(u32, &mut [u8]) + Send + Sync) {
+    let row_len = image.width() as usize * 4;
+    if row_len == 0 {
+        return;
+    }
+    let pixel_count = image.width() as usize * image.height() as usize;
+    let subpixels: &mut [u8] = image;
+    let map_band = |(i, band): (usize, &mut [u8])| map_rows((i * ROWS_PER_BAND) as u32, band);
+    if pixel_count < PARALLEL_MIN_PIXELS {
+        subpixels.chunks_mut(row_len * ROWS_PER_BAND).enumerate().for_each(map_band);
+    } else {
+        subpixels.par_chunks_mut(row_len * ROWS_PER_BAND).enumerate().for_each(map_band);
+    }
+}
+
+/// threshold map with values from -0.5 to 0.5, size is a power of two
+fn bayer_matrix(size: usize) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0u32]];
+    while matrix.len() < size {
+        let n = matrix.len();
+        let mut next = vec![vec![0u32; n * 2]; n * 2];
+        for y in 0..n {
+            for x in 0..n {
+                let value = 4 * matrix[y][x];
+                next[y][x] = value;
+                next[y][x + n] = value + 2;
+                next[y + n][x] = value + 3;
+                next[y + n][x + n] = value + 1;
+            }
+        }
+        matrix = next;
+    }
+    let cells = (matrix.len() * matrix.len()) as f32;
+    matrix
+        .into_iter()
+        .map(|row| row.into_iter().map(|value| (value as f32 + 0.5) / cells - 0.5).collect())
+        .collect()
+}
+
+/// nearest palette colors for a 32x32x32 cube over RGB, looked up by the top 5 bits of every channel.
+/// Colors are matched by the center of their cell, up to 4 steps per channel away from them, so the
+/// color found is at most 2·√48 ≈ 14 RGB steps further away than the nearest one. Small images are
+/// looked up exactly with nearest_palette_color, building the cube costs more than it saves for them
+pub struct NearestColorLut {
+    cells: Vec<(u8, u8, u8)>,
+}
+
+impl NearestColorLut {
+    const BITS: u32 = 5;
+    const SIDE: usize = 1 << Self::BITS;
+    /// the worst case distance between a color and the center of its cell, times two
+    pub const TOLERANCE: f32 = 13.856_406;
+
+    pub fn new(colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) -> Self {
+        let shift = 8 - Self::BITS;
+        let center = |i: usize| ((i << shift) + (1 << (shift - 1))) as u8;
+        let cells = (0..Self::SIDE * Self::SIDE * Self::SIDE)
+            .into_par_iter()
+            .map(|i| {
+                let (r, g, b) = (i / (Self::SIDE * Self::SIDE), (i / Self::SIDE) % Self::SIDE, i % Self::SIDE);
+                let color = RgbColor { r: center(r), g: center(g), b: center(b) };
+                let (_, nearest) = nearest_palette_color(color, colors, metric, max_brightness);
+                (nearest.r, nearest.g, nearest.b)
+            })
+            .collect();
+        NearestColorLut { cells }
+    }
+
+    /// a cube for images with at least as many pixels as it has cells, none for smaller ones
+    pub fn for_image(image: &RgbaImage, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) -> Option<Self> {
+        let pixels = image.width() as usize * image.height() as usize;
+        (pixels >= Self::SIDE * Self::SIDE * Self::SIDE).then(|| Self::new(colors, metric, max_brightness))
     }
 
-    println!("greyscale: {:.3} - {:.3}", smallest_grey, biggest_grey);
+    pub fn get(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        let shift = 8 - Self::BITS;
+        let (r, g, b) = ((r >> shift) as usize, (g >> shift) as usize, (b >> shift) as usize);
+        self.cells[(r * Self::SIDE + g) * Self::SIDE + b]
+    }
+}
+
+fn ordered_dither(
+    image: &mut RgbaImage, 
+    colors: &PaletteColors, 
+    metric: DistanceMetric, 
+    size: u8, 
+    strength: u8, 
+    max_brightness: f32
+) {
+    // how far a threshold can move a channel at full strength
+    const SPREAD: f32 = 64.0;
+    let spread = SPREAD * strength.min(100) as f32 / 100.0;
+    let lut = NearestColorLut::new(colors, metric, max_brightness);
+    let matrix = bayer_matrix((size as usize).clamp(2, 8).next_power_of_two());
+    let n = matrix.len();
+    let width = image.width() as usize;
+    for_each_row_band(image, |first_y, pixels| {
+        for (i, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let (x, y) = (i % width, first_y as usize + i / width);
+            let offset = matrix[y % n][x % n] * spread;
+            let shift = |channel: u8| (channel as f32 + offset).clamp(0.0, 255.0) as u8;
+            let (r, g, b) = lut.get(shift(pixel[0]), shift(pixel[1]), shift(pixel[2]));
+            pixel[0] = r;
+            pixel[1] = g;
+            pixel[2] = b;
+        }
+    });
+}
+
+fn floyd_steinberg_dither(image: &mut RgbaImage, colors: &PaletteColors, metric: DistanceMetric, max_brightness: f32) {
+    let (width, height) = image.dimensions();
+    // errors of the current and the next row, padded by one pixel on both sides
+    let mut errors = vec![[0f32; 3]; width as usize + 2];
+    let mut next_errors = vec![[0f32; 3]; width as usize + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let i = x as usize + 1;
+            let pixel = image.get_pixel_mut(x, y);
+            // transparent pixels neither take nor pass on any error
+            if pixel[3] == 0 {
+                continue;
+            }
+            let wanted = [
+                pixel[0] as f32 + errors[i][0],
+                pixel[1] as f32 + errors[i][1],
+                pixel[2] as f32 + errors[i][2],
+            ];
+            let color = RgbColor {
+                r: wanted[0].clamp(0.0, 255.0) as u8,
+                g: wanted[1].clamp(0.0, 255.0) as u8,
+                b: wanted[2].clamp(0.0, 255.0) as u8,
+            };
+            let (_, nearest_color) = nearest_palette_color(color, colors, metric, max_brightness);
+            let emitted = [nearest_color.r, nearest_color.g, nearest_color.b];
+            for c in 0..3 {
+                let error = wanted[c] - emitted[c] as f32;
+                errors[i + 1][c] += error * 7.0 / 16.0;
+                next_errors[i - 1][c] += error * 3.0 / 16.0;
+                next_errors[i][c] += error * 5.0 / 16.0;
+                next_errors[i + 1][c] += error * 1.0 / 16.0;
+                pixel[c] = emitted[c];
+            }
+        }
+        std::mem::swap(&mut errors, &mut next_errors);
+        next_errors.fill([0.0; 3]);
+    }
 }
 
 fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
@@ -803,6 +2487,51 @@ pub struct Brightness {
     pub max: f32,
 }
 
+/// the `k` most common colors of the visible pixels with their share (0.0 - 1.0), most common first.
+/// Similar colors share a bucket of 4 bits per channel and are averaged, measured on a downscaled copy
+pub fn dominant_colors(image: &RgbaImage, k: usize) -> Vec<(RgbColor, f32)> {
+    let sample = downsample_for_analysis(image);
+    // channel sums and pixel count per bucket
+    let mut buckets: HashMap<(u8, u8, u8), [u64; 4]> = HashMap::new();
+    for Rgba([r, g, b, a]) in sample.pixels() {
+        if *a == 0 {
+            continue;
+        }
+        let bucket = buckets.entry((r >> 4, g >> 4, b >> 4)).or_insert([0; 4]);
+        bucket[0] += *r as u64;
+        bucket[1] += *g as u64;
+        bucket[2] += *b as u64;
+        bucket[3] += 1;
+    }
+    let visible = buckets.values().map(|bucket| bucket[3]).sum::<u64>().max(1) as f32;
+    let mut buckets: Vec<[u64; 4]> = buckets.into_values().collect();
+    buckets.sort_by(|b1, b2| b2[3].cmp(&b1[3]));
+    buckets
+        .into_iter()
+        .take(k)
+        .map(|[r, g, b, count]| {
+            let color = RgbColor::new((r / count) as u8, (g / count) as u8, (b / count) as u8);
+            (color, count as f32 / visible)
+        })
+        .collect()
+}
+
+/// pixels need more alpha than this to count as visible, the others are left out of the measurements
+pub const VISIBLE_ALPHA: u8 = 128;
+
+/// longer side of the copy the image information is measured on
+const ANALYSIS_MAX_SIDE: u32 = 256;
+
+fn downsample_for_analysis(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width.max(height) <= ANALYSIS_MAX_SIDE {
+        return image.clone();
+    }
+    let scale = ANALYSIS_MAX_SIDE as f32 / width.max(height) as f32;
+    let (width, height) = (((width as f32 * scale) as u32).max(1), ((height as f32 * scale) as u32).max(1));
+    image::imageops::thumbnail(image, width, height)
+}
+
 fn get_image_information(image: &RgbaImage) -> ImageInformation {
     let mut total_brightness = 0.0;
     let mut total_grayscale = 0.0;
@@ -813,16 +2542,17 @@ fn get_image_information(image: &RgbaImage) -> ImageInformation {
     let mut min_grayscale = f32::MAX;
     let mut max_grayscale = f32::MIN;
     
-    let num_pixels = image.width() * image.height();
     const SAMPLE_DISTANCE: usize = 50;
-    let pixel_amount = num_pixels / SAMPLE_DISTANCE.max(1) as u32;
+    // transparent pixels aren't seen, whatever color they store doesn't count
+    let mut pixel_amount: u32 = 0;
 
     for (i, Rgba([r, g, b, a])) in image.pixels().enumerate() {
-        if i % SAMPLE_DISTANCE != 0 || *a <= 128 {
+        if i % SAMPLE_DISTANCE != 0 || *a <= VISIBLE_ALPHA {
             continue;
         }
+        pixel_amount += 1;
         let pixel = RgbColor { r: *r, g: *g, b: *b };
-        let brightness = pixel.brightness();
+        let brightness = pixel.perceived_lightness();
         let grayscale_similarity = pixel.calculate_grayscale_similarity();
 
         total_brightness += brightness;
@@ -844,6 +2574,11 @@ fn get_image_information(image: &RgbaImage) -> ImageInformation {
         *color_map.entry((pixel.r, pixel.g, pixel.b)).or_insert(0) += 1;
     }
 
+    if pixel_amount == 0 {
+        // nothing visible, like a fully transparent image
+        (min_brightness, max_brightness, min_grayscale, max_grayscale) = (0.0, 0.0, 0.0, 0.0);
+    }
+    let pixel_amount = pixel_amount.max(1);
     let average_brightness = total_brightness / pixel_amount as f32;
     let average_grayscale_similarity = total_grayscale / pixel_amount as f32;
 
@@ -909,10 +2644,10 @@ fn segment_image<'a>(
 > 
 {
     let input_tensor = preprocess_image(image, &options);
-    println!("Input tensor shape: {:?}", input_tensor.shape());
+    debug!("Segmenting input_shape={:?}", input_tensor.shape());
     let input_array = vec![input_tensor];
     let output: Vec<OrtOwnedTensor<f32, ndarray::Dim<ndarray::IxDynImpl>>> = session.run(input_array).unwrap();
-    println!("Output tensor shape: {:?}", output[0].shape());
+    debug!("Segmented output_shape={:?}", output[0].shape());
     let tensor = output.into_iter().next().unwrap();
     Ok(tensor)
 }
@@ -972,7 +2707,7 @@ fn apply_mask(
         let alpha = activation_function(mask_value);
         pixel.copy_from_slice(&[r, g, b, alpha]);
     });
-    println!("[Masking-loop] Time taken: {:.3} seconds", start.elapsed().as_secs_f32());
+    debug!("Applied mask elapsed_ms={}", start.elapsed().as_millis());
     let img = DynamicImage::ImageRgba8(masked_image);
     img
 }
@@ -982,9 +2717,501 @@ pub fn remove_background<'a>(mut session: Session<'_>, image: DynamicImage, opti
     // start time
     let start = std::time::Instant::now();
     let mask = segment_image(&mut session, &image, &options).unwrap();
-    println!("[Segmentation] Time taken: {:.3} seconds", start.elapsed().as_secs_f32());
+    debug!("Segmentation done elapsed_ms={}", start.elapsed().as_millis());
     let start = std::time::Instant::now();
     let segmented_image = apply_mask(&image, &mask, &options);
-    println!("[Masking] Time taken: {:.3} seconds", start.elapsed().as_secs_f32());
+    debug!("Masking done elapsed_ms={}", start.elapsed().as_millis());
     segmented_image
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// the brightest color the palette maps greyish pixels onto
+    fn lightest_dark(colors: &PaletteColors) -> RgbColor {
+        colors.darks.iter().max_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness())).cloned().unwrap()
+    }
+
+    #[test]
+    fn white_maps_to_the_lightest_swatch() {
+        let white = RgbColor::new(255, 255, 255);
+        for palette in Palette::iter().into_iter().filter(|palette| *palette != Palette::Custom) {
+            let colors = PaletteColors::from_palette(palette);
+            // the same cap apply_nord_with_colors uses
+            let max_brightness = if palette.is_light() { 1.0 } else { 0.85 };
+            let (_, nearest) = nearest_palette_color(white.clone(), &colors, DistanceMetric::Brightness, max_brightness);
+            assert_eq!(*nearest, lightest_dark(&colors), "{}", palette.name());
+        }
+    }
+
+    #[test]
+    fn custom_id_round_trip() {
+        let options = NordOptions {
+            palette: Palette::CatppuccinMacchiato,
+            format: OutputFormat::Jpeg(85),
+            dither: Dither::Ordered(8, 40),
+            strength: 0.65,
+            compare: true,
+            smooth: true,
+            hue_rotate: 180.0,
+            ..NordOptions::new()
+        };
+        let custom_id = options.make_nord_custom_id(&1234567890123456789, true, Some(7));
+        let button = NordButton::decode(&custom_id).unwrap();
+        assert!(button.update);
+        assert_eq!(button.id, 7);
+        assert_eq!(button.message_id, 1234567890123456789);
+        assert_eq!(button.options.make_nord_custom_id(&1234567890123456789, true, Some(7)), custom_id);
+        assert_eq!(button.options.palette, Palette::CatppuccinMacchiato);
+        assert_eq!(button.options.dither, Dither::Ordered(8, 40));
+        assert_eq!(button.options.strength, 0.65);
+        assert!(button.options.compare && button.options.smooth);
+    }
+
+    #[test]
+    fn longest_custom_id_fits_into_discord() {
+        let options = NordOptions {
+            palette: Palette::SolarizedLight,
+            format: OutputFormat::Jpeg(100),
+            dither: Dither::Ordered(16, 99),
+            preserve_luminance: true, compare: true, grayscale: true, auto_contrast: true,
+            dim: true, gradient_map: true, bright_only: true, smooth: true,
+            invert: true, sepia: true, nord: true, erase_most_present_color: true,
+            auto_adjust: true, start: true, hue_rotate: 180.0,
+            ..NordOptions::new()
+        };
+        let custom_id = options.make_nord_custom_id(&u64::MAX, true, Some(99));
+        assert!(custom_id.len() <= CUSTOM_ID_MAX_LEN, "{} characters: {custom_id}", custom_id.len());
+    }
+
+    #[test]
+    fn rejects_malformed_custom_ids() {
+        assert!(NordButton::decode("darken-nord-webp").is_none());
+        assert!(NordButton::decode("stop-1234").is_none());
+    }
+
+    #[test]
+    fn near_identical_grays_snap_to_the_same_swatch() {
+        let neutrals = [Palette::Nord.darks(), Palette::Nord.lights()].concat();
+        let gray = get_nearest_color(&RgbColor::new(128, 128, 128), &neutrals, DistanceMetric::Ciede2000);
+        let lighter_gray = get_nearest_color(&RgbColor::new(130, 130, 130), &neutrals, DistanceMetric::Ciede2000);
+        assert_eq!(gray, lighter_gray);
+        assert_eq!(gray.to_hex(), "#4c566a");
+    }
+
+    #[test]
+    fn ciede2000_keeps_navy_blue_where_rgb_picks_gray() {
+        let colors = [RgbColor::new(0, 0, 255), RgbColor::new(90, 90, 90)];
+        let navy = RgbColor::new(40, 40, 120);
+        assert_eq!(get_nearest_color(&navy, &colors, DistanceMetric::Ciede2000), &colors[0]);
+        assert_eq!(get_nearest_color(&navy, &colors, DistanceMetric::RgbEuclidean), &colors[1]);
+    }
+
+    /// black to white from left to right
+    fn gray_gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, _| {
+            let value = (x * 255 / (width - 1)) as u8;
+            Rgba([value, value, value, 255])
+        })
+    }
+
+    /// distinct colors of the image which are colors of the palette
+    fn palette_colors_used(image: &RgbaImage, palette: &[RgbColor]) -> usize {
+        ConversionStats::from_image(image)
+            .color_counts
+            .keys()
+            .filter(|(r, g, b)| palette.contains(&RgbColor::new(*r, *g, *b)))
+            .count()
+    }
+
+    #[test]
+    fn dithering_spreads_gradients_over_more_palette_colors() {
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        let mut plain = gray_gradient(64, 8);
+        apply_palette_filter(&mut plain, &colors, DistanceMetric::Brightness, Dither::None, 0.85, false, false);
+        let mut dithered = gray_gradient(64, 8);
+        apply_palette_filter(&mut dithered, &colors, DistanceMetric::Brightness, Dither::FloydSteinberg, 0.85, false, false);
+
+        // Floyd-Steinberg only emits palette colors
+        assert!(dithered.pixels().all(|Rgba([r, g, b, _])| colors.darks.contains(&RgbColor::new(*r, *g, *b))));
+        let dithered_colors = palette_colors_used(&dithered, &colors.darks);
+        assert_eq!(dithered_colors, colors.darks.len());
+        assert!(dithered_colors > palette_colors_used(&plain, &colors.darks));
+    }
+
+    fn sample_photo() -> RgbaImage {
+        RgbaImage::from_fn(32, 16, |x, y| Rgba([(x * 8) as u8, (y * 16) as u8, 200, 255]))
+    }
+
+    #[test]
+    fn strength_mixes_conversion_into_the_original() {
+        let original = sample_photo();
+        let info = calculate_average_brightness(&original);
+        let convert = |strength| {
+            let options = NordOptions { strength, ..NordOptions::default() };
+            apply_nord(DynamicImage::ImageRgba8(original.clone()), options, &info).to_rgba8()
+        };
+        let full = convert(1.0);
+        assert_ne!(full, original);
+        assert_eq!(convert(0.0), original);
+
+        let half = convert(0.5);
+        for ((half, full), original) in half.pixels().zip(full.pixels()).zip(original.pixels()) {
+            for c in 0..3 {
+                assert!(half[c] >= full[c].min(original[c]) && half[c] <= full[c].max(original[c]));
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_images_are_downscaled_to_the_cap() {
+        let image = fit_within(DynamicImage::new_luma8(4000, 3000), 2048);
+        assert_eq!((image.width(), image.height()), (2048, 1536));
+
+        let small = fit_within(DynamicImage::new_luma8(640, 480), 2048);
+        assert_eq!((small.width(), small.height()), (640, 480));
+    }
+
+    /// colorful squares of 4px, alternating between invisible, half transparent and opaque
+    fn alpha_checkerboard() -> RgbaImage {
+        RgbaImage::from_fn(32, 32, |x, y| {
+            let alpha = [0, 128, 255][((x / 4 + y / 4) % 3) as usize];
+            Rgba([(x * 8) as u8, 100, (y * 8) as u8, alpha])
+        })
+    }
+
+    #[test]
+    fn alpha_survives_every_mode() {
+        let original = alpha_checkerboard();
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        let dithers = [Dither::None, Dither::Ordered(4, 100), Dither::FloydSteinberg, Dither::TwoColor];
+        for (dither, preserve_luminance, grayscale) in dithers.into_iter()
+            .flat_map(|dither| [(dither, false, false), (dither, true, false), (dither, false, true)])
+        {
+            let mut converted = original.clone();
+            apply_palette_filter(&mut converted, &colors, DistanceMetric::Brightness, dither, 0.85, preserve_luminance, grayscale);
+            for (converted, original) in converted.pixels().zip(original.pixels()) {
+                assert_eq!(converted[3], original[3], "{}", dither.name());
+                // invisible pixels keep their color, so scaling doesn't pull palette colors into the edges
+                if original[3] == 0 {
+                    assert_eq!(converted, original, "{}", dither.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recognizes_images_already_in_the_palette() {
+        let colors = Palette::Nord.colors();
+        let nord = RgbaImage::from_fn(64, 64, |x, y| colors[((x / 8 + y / 8) as usize) % colors.len()].to_rgba());
+        assert!(is_already_converted(&nord, Palette::Nord));
+
+        assert!(!is_already_converted(&noise(64, 64), Palette::Nord));
+    }
+
+    #[test]
+    fn preserving_luminance_keeps_the_brightness_of_every_pixel() {
+        let original = RgbaImage::from_fn(64, 32, |x, y| Rgba([80 + x as u8, 90 + 2 * y as u8, 120 + x as u8, 255]));
+        let luminance = |Rgba([r, g, b, _]): &Rgba<u8>| 0.299 * *r as f32 + 0.587 * *g as f32 + 0.114 * *b as f32;
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        let mut converted = original.clone();
+        apply_palette_filter(&mut converted, &colors, DistanceMetric::Brightness, Dither::None, 0.85, true, false);
+
+        assert_ne!(converted, original);
+        for (converted, original) in converted.pixels().zip(original.pixels()) {
+            // every channel is rounded on its own
+            assert!((luminance(converted) - luminance(original)).abs() <= 2.0, "{converted:?} {original:?}");
+        }
+    }
+
+    #[test]
+    fn stats_count_the_distinct_output_colors() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]));
+        let palette = [[46, 52, 64], [136, 192, 208], [191, 97, 106], [236, 239, 244]];
+        let (converted, stats) = apply_palette_with_stats(
+            DynamicImage::ImageRgba8(image), &palette, DistanceMetric::RgbEuclidean, Dither::FloydSteinberg, false, false
+        );
+        let distinct: HashSet<[u8; 4]> = converted.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+        assert_eq!(stats.colors_used, distinct.len());
+        assert_eq!(stats.pixels, 32 * 32);
+
+        // invisible pixels don't count
+        let mut image = RgbaImage::from_pixel(4, 1, Rgba([46, 52, 64, 255]));
+        image.put_pixel(1, 0, Rgba([236, 239, 244, 255]));
+        image.put_pixel(2, 0, Rgba([191, 97, 106, 0]));
+        assert_eq!(ConversionStats::from_image(&image).colors_used, 2);
+    }
+
+    #[test]
+    fn grayscale_ignores_the_hue() {
+        // both have a brightness of about 0.245
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([209, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 57, 255, 255]));
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        apply_palette_filter(&mut image, &colors, DistanceMetric::Ciede2000, Dither::None, 0.85, false, true);
+
+        let (red, blue) = (image.get_pixel(0, 0), image.get_pixel(1, 0));
+        assert_eq!(red, blue);
+        assert!(colors.neutrals.contains(&RgbColor::new(red[0], red[1], red[2])));
+    }
+
+    #[test]
+    fn output_scale_resizes_within_the_limit() {
+        let image = || DynamicImage::new_rgba8(400, 300);
+        let dimensions = |image: DynamicImage| (image.width(), image.height());
+        assert_eq!(dimensions(scale_output(image(), 0.5, 2048)), (200, 150));
+        assert_eq!(dimensions(scale_output(image(), 2.0, 2048)), (800, 600));
+        assert_eq!(dimensions(scale_output(image(), 1.0, 2048)), (400, 300));
+        // clamped to 0.25 - 2.0
+        assert_eq!(dimensions(scale_output(image(), 0.1, 2048)), (100, 75));
+        assert_eq!(dimensions(scale_output(image(), 8.0, 2048)), (800, 600));
+        // never upscaled past the input limit
+        assert_eq!(dimensions(scale_output(image(), 2.0, 600)), (600, 450));
+    }
+
+    #[test]
+    fn auto_contrast_stretches_dull_images() {
+        // grays from 100 to 150
+        let dull = RgbaImage::from_fn(102, 10, |x, _| {
+            let value = 100 + (x / 2) as u8;
+            Rgba([value, value, value, 255])
+        });
+        let range = |image: &RgbaImage| {
+            let values = image.pixels().map(|pixel| pixel[0]);
+            (values.clone().min().unwrap(), values.max().unwrap())
+        };
+        assert_eq!(range(&dull), (100, 150));
+        let (min, max) = range(&auto_contrast(DynamicImage::ImageRgba8(dull), 1.0, 1.0).to_rgba8());
+        assert!(min <= 5 && max >= 250, "{min} - {max}");
+    }
+
+    #[test]
+    fn grays_are_about_as_light_as_their_channels() {
+        // the thresholds were tuned on the mean of the channels and still hold on the L* scale
+        for value in [64u8, 128, 192] {
+            let gray = RgbaImage::from_pixel(64, 64, Rgba([value, value, value, 255]));
+            let lightness = calculate_average_brightness(&gray).brightness.average;
+            assert!((lightness - value as f32 / 255.0).abs() < 0.04, "{value}: {lightness}");
+        }
+    }
+
+    #[test]
+    fn hsl_keeps_orange_warm() {
+        let orange = RgbColor::new(255, 128, 0);
+        let colors = Palette::Nord.colors();
+        // red, orange and yellow of the aurora
+        let warm = &NORD_AURORA[..3];
+        let nearest = get_nearest_color(&orange, &colors, DistanceMetric::Hsl);
+        assert!(warm.contains(nearest), "{}", nearest.to_hex());
+        let (hue, _, _) = nearest.to_hsl();
+        assert!(hue < 60.0, "{hue}");
+    }
+
+    /// the same noise every run from a tiny LCG, opaque
+    fn noise(width: u32, height: u32) -> RgbaImage {
+        let mut seed = 0x9e37_79b9u32;
+        RgbaImage::from_fn(width, height, |_, _| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let [r, g, b, _] = seed.to_be_bytes();
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    #[test]
+    fn parallel_mapping_matches_a_single_thread() {
+        let image = noise(512, 512);
+        assert!((image.width() * image.height()) as usize >= PARALLEL_MIN_PIXELS);
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        let single_thread = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let all_threads = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for dither in [Dither::None, Dither::Ordered(4, 100), Dither::TwoColor] {
+            let convert = |pool: &rayon::ThreadPool| pool.install(|| {
+                let mut converted = image.clone();
+                apply_palette_filter(&mut converted, &colors, DistanceMetric::RgbEuclidean, dither, 0.85, true, false);
+                converted
+            });
+            assert!(convert(&single_thread) == convert(&all_threads), "{}", dither.name());
+        }
+    }
+
+    #[test]
+    fn dimming_reaches_the_target_brightness() {
+        // small enough to be measured without downsampling, like dim_to_brightness measures it
+        let bright = RgbaImage::from_fn(240, 160, |x, y| Rgba([255 - (x / 4) as u8, 230, 180 + (y / 4) as u8, 255]));
+        for target in [0.2, 0.4, 0.6] {
+            let dimmed = dim_to_brightness(DynamicImage::ImageRgba8(bright.clone()), target).to_rgba8();
+            let brightness = calculate_average_brightness(&dimmed).brightness.average;
+            assert!(brightness <= target, "{brightness} > {target}");
+            // not much darker than needed either
+            assert!(brightness > target - 0.05, "{brightness} for {target}");
+        }
+
+        let dark = RgbaImage::from_pixel(16, 16, Rgba([30, 40, 50, 255]));
+        assert_eq!(dim_to_brightness(DynamicImage::ImageRgba8(dark.clone()), 0.4).to_rgba8(), dark);
+    }
+
+    #[test]
+    fn tiles_repeat_without_scaling() {
+        let source = noise(100, 100);
+        let tiled = tile_to(&DynamicImage::ImageRgba8(source.clone()), 250, 250).to_rgba8();
+        assert_eq!(tiled.dimensions(), (250, 250));
+        assert_eq!(image::imageops::crop_imm(&tiled, 0, 0, 100, 100).to_image(), source);
+        // the third tile is cut off after half of the source
+        assert_eq!(image::imageops::crop_imm(&tiled, 200, 200, 50, 50).to_image(), image::imageops::crop_imm(&source, 0, 0, 50, 50).to_image());
+    }
+
+    #[test]
+    fn palette_images_have_no_distance_to_the_palette() {
+        let colors = Palette::Nord.colors();
+        let nord = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, _| colors[x as usize].to_rgba()));
+        let photo = DynamicImage::ImageRgba8(noise(32, 32));
+        for metric in DistanceMetric::iter() {
+            assert_eq!(analyze_conversion(&nord, Palette::Nord, metric), (0.0, 0.0), "{}", metric.as_str());
+            let (mean, max) = analyze_conversion(&photo, Palette::Nord, metric);
+            assert!(mean > 0.0 && mean <= max, "{}: {mean} {max}", metric.as_str());
+        }
+    }
+
+    #[test]
+    fn two_color_blend_mixes_the_nearest_pair_evenly() {
+        let colors = PaletteColors::from_rgb(&[[40, 40, 40], [120, 120, 120], [230, 230, 230]]);
+        let halfway = RgbColor::new(80, 80, 80);
+        assert_eq!(two_color_pixel(halfway, &colors, DistanceMetric::RgbEuclidean, 0.85), (80, 80, 80));
+        // a quarter of the way the nearer color gets three quarters
+        let quarter = RgbColor::new(60, 60, 60);
+        assert_eq!(two_color_pixel(quarter, &colors, DistanceMetric::RgbEuclidean, 0.85), (60, 60, 60));
+        // on a palette color there is nothing to mix
+        let exact = RgbColor::new(120, 120, 120);
+        assert_eq!(two_color_pixel(exact, &colors, DistanceMetric::RgbEuclidean, 0.85), (120, 120, 120));
+    }
+
+    #[test]
+    fn bayer_dithering_is_deterministic_and_spreads_gradients() {
+        let colors = PaletteColors::from_palette(Palette::Nord);
+        let convert = |dither| {
+            let mut image = gray_gradient(64, 8);
+            apply_palette_filter(&mut image, &colors, DistanceMetric::Brightness, dither, 0.85, false, false);
+            image
+        };
+        let plain = convert(Dither::None);
+        let ordered = convert(Dither::Ordered(8, 100));
+        assert_eq!(ordered.as_raw(), convert(Dither::Ordered(8, 100)).as_raw());
+
+        // the threshold map only decides between palette colors
+        assert!(ordered.pixels().all(|Rgba([r, g, b, _])| colors.darks.contains(&RgbColor::new(*r, *g, *b))));
+        assert!(palette_colors_used(&ordered, &colors.darks) > palette_colors_used(&plain, &colors.darks));
+    }
+
+    #[test]
+    fn lab_matching_disagrees_with_rgb_where_perception_does() {
+        // (pixel, palette, index picked in Lab, index picked in RGB)
+        let cases = [
+            // a dim blue is still blue, not the gray that is closer in RGB
+            ((60, 60, 140), [(0, 0, 255), (90, 90, 90)], 0, 1),
+            // skin tones shouldn't go olive
+            ((230, 180, 150), [(200, 200, 140), (255, 150, 150)], 1, 0),
+        ];
+        for ((r, g, b), palette, lab_index, rgb_index) in cases {
+            let palette: Vec<RgbColor> = palette.iter().map(|(r, g, b)| RgbColor::new(*r, *g, *b)).collect();
+            let pixel = RgbColor::new(r, g, b);
+            assert_eq!(get_nearest_color(&pixel, &palette, DistanceMetric::Ciede2000), &palette[lab_index]);
+            assert_eq!(get_nearest_color(&pixel, &palette, DistanceMetric::RgbEuclidean), &palette[rgb_index]);
+        }
+    }
+
+    #[test]
+    fn pure_colors_have_their_known_luminance() {
+        // (color, Rec. 709 relative luminance, CIE lightness)
+        let cases = [
+            ([255, 0, 0], 0.2126, 0.5323),
+            ([0, 255, 0], 0.7152, 0.8774),
+            ([0, 0, 255], 0.0722, 0.3230),
+            ([255, 255, 255], 1.0, 1.0),
+            ([0, 0, 0], 0.0, 0.0),
+        ];
+        for ([r, g, b], luminance, lightness) in cases {
+            assert!((RgbColor::new(r, g, b).relative_luminance() - luminance).abs() < 1e-3, "{r} {g} {b}");
+            // bigger than the analysis size, so the downsampled copy is measured
+            let image = RgbaImage::from_pixel(400, 300, Rgba([r, g, b, 255]));
+            let average = calculate_average_brightness(&image).brightness.average;
+            assert!((average - lightness).abs() < 1e-3, "{r} {g} {b}: {average}");
+        }
+    }
+
+    #[test]
+    fn lut_stays_within_its_tolerance_of_brute_force() {
+        // one list for every group, so a color and the center of its cell always see the same candidates
+        let nord: Vec<[u8; 3]> = Palette::Nord.colors().iter().map(|color| [color.r, color.g, color.b]).collect();
+        let colors = PaletteColors::from_rgb(&nord);
+        let metric = DistanceMetric::RgbEuclidean;
+        let lut = NearestColorLut::new(&colors, metric, 1.0);
+        let mut exact = 0;
+        let image = noise(64, 64);
+        for Rgba([r, g, b, _]) in image.pixels() {
+            let color = RgbColor::new(*r, *g, *b);
+            let (_, nearest) = nearest_palette_color(color.clone(), &colors, metric, 1.0);
+            let (lr, lg, lb) = lut.get(*r, *g, *b);
+            let looked_up = RgbColor::new(lr, lg, lb);
+            let extra = metric.distance(&color, &looked_up) - metric.distance(&color, nearest);
+            assert!(extra <= NearestColorLut::TOLERANCE, "{r} {g} {b}: {extra}");
+            if &looked_up == nearest {
+                exact += 1;
+            }
+        }
+        // most colors are nowhere near the border between two palette colors
+        assert!(exact * 10 >= image.len() / 4 * 9, "{exact}");
+    }
+
+    /// dark 1px strokes on white, like text in a screenshot
+    fn screenshot_text() -> RgbaImage {
+        RgbaImage::from_fn(24, 12, |x, y| {
+            // an "I", a "T" and a "-"
+            let stroke = (x == 4 && (2..10).contains(&y))
+                || (y == 2 && (8..15).contains(&x))
+                || (x == 11 && (2..10).contains(&y))
+                || (y == 6 && (17..22).contains(&x));
+            if stroke { Rgba([46, 52, 64, 255]) } else { Rgba([255, 255, 255, 255]) }
+        })
+    }
+
+    #[test]
+    fn smoothing_keeps_thin_text_and_calms_noise() {
+        let text = screenshot_text();
+        let smoothed = smooth_noise(DynamicImage::ImageRgba8(text.clone()), SMOOTH_RADIUS).to_rgba8();
+        assert_eq!(smoothed, text);
+
+        // gray with a few steps of sensor noise
+        let mut seed = 1u32;
+        let noisy = RgbaImage::from_fn(32, 32, |_, _| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let value = 120 + (seed >> 28) as u8;
+            Rgba([value, value, value, 255])
+        });
+        let spread = |image: &RgbaImage| {
+            let values: Vec<f32> = image.pixels().map(|Rgba([r, _, _, _])| *r as f32).collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            values.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / values.len() as f32
+        };
+        let calmed = smooth_noise(DynamicImage::ImageRgba8(noisy.clone()), SMOOTH_RADIUS).to_rgba8();
+        assert!(spread(&calmed) < spread(&noisy) / 2.0, "{} {}", spread(&calmed), spread(&noisy));
+    }
+
+    #[test]
+    fn color_groups_leave_no_other_colors_behind() {
+        let groups = [ColorGroup::PolarNight, ColorGroup::Frost];
+        let colors = PaletteColors::from_palette_groups(Palette::Nord, &groups).unwrap();
+        let kept: Vec<RgbColor> = groups.iter().flat_map(|group| group.colors(Palette::Nord).iter().cloned()).collect();
+        let image = noise(64, 64);
+        let info = calculate_average_brightness(&image);
+        for dither in [Dither::None, Dither::Ordered(8, 100), Dither::FloydSteinberg, Dither::TwoColor] {
+            // strength and bright_only would mix the original back in
+            let options = NordOptions { snap: true, dither, strength: 0.5, bright_only: true, ..NordOptions::default() };
+            let converted = apply_nord_with_colors(DynamicImage::ImageRgba8(image.clone()), options, &info, &colors).to_rgba8();
+            for Rgba([r, g, b, a]) in converted.pixels() {
+                assert!(*a == 0 || kept.contains(&RgbColor::new(*r, *g, *b)), "{}: {r} {g} {b}", dither.name());
+            }
+        }
+    }
+}
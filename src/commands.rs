@@ -1,7 +1,12 @@
+use std::time::Instant;
+
 use poise::CreateReply;
-use serenity::all::{CreateAttachment, Message};
+use serenity::all::{Attachment, CreateAttachment, CreateMessage, GetMessages, Message};
 
-use crate::{colors::NordOptions, fetch_image_and_info, process_attachments, tickbox::TickBox, AsyncError, Context};
+use crate::{
+    colors::{self, ColorGroup, DistanceMetric, Dither, NordOptions, Palette, PaletteColors, RgbColor}, config::{self, parse_hex_colors, validate_threshold}, convert_image, encode::{encode_image, EncodeOptions, OutputFormat, PngCompression}, encode_processed, fetch_image_and_info, fetch_url_and_info, image_check, process_image, ConversionExtras, 
+    messages::{self, fill, Messages}, palettes::PaletteRegistry, process_attachments, stats::format_count, swatches::{render_color_swatches, render_swatches}, tickbox::TickBox, AsyncError, Context
+};
 
 /// Show this help menu
 #[poise::command(prefix_command, track_edits, slash_command)]
@@ -45,15 +50,708 @@ pub async fn edit_message_image(
     reply.edit(ctx, CreateReply::default().content(&tickbox.to_string())).await?;
     let mut options = NordOptions::from_image_information(&info);
     options.start = true;
-    let buffer = process_attachments(&message, ctx.data(), &options).await?;
+    let processed = process_attachments(&message, ctx.guild_id(), ctx.data(), &options, &|_| {}).await?;
     tickbox.next();
     reply.edit(ctx, CreateReply::default().content(&tickbox.to_string())).await?;
+    let mut response = CreateReply::default()
+        .content(format!(
+            "{}{}",
+            processed.skipped_note(&ctx.data().messages(ctx.guild_id())), processed.downscaled_note(ctx.data().config().limits.max_dimension)
+        ))
+        .components(options.build_componets(u64::from(message.id), true));
+    for attachment in processed.files {
+        response = response.attachment(attachment);
+    }
+    ctx.send(response).await?;
+    reply.delete(ctx).await?;
+    Ok(())
+}
+
+
+
+/// sizes /nord can tile the result to
+#[derive(Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum WallpaperSize {
+    #[name = "Full HD (1920x1080)"]
+    FullHd,
+    #[name = "QHD (2560x1440)"]
+    Qhd,
+    #[name = "4K (3840x2160)"]
+    Uhd,
+    #[name = "Phone (1080x1920)"]
+    Phone,
+}
+
+/// the output formats /nord offers
+#[derive(Clone, Copy, Debug, poise::ChoiceParameter)]
+pub enum FormatChoice {
+    #[name = "PNG"]
+    Png,
+    #[name = "PNG with a palette, smallest for flat images"]
+    IndexedPng,
+    #[name = "JPEG"]
+    Jpeg,
+    #[name = "WebP"]
+    WebP,
+}
+
+impl FormatChoice {
+    pub fn output_format(&self) -> OutputFormat {
+        match self {
+            FormatChoice::Png => OutputFormat::Png,
+            FormatChoice::IndexedPng => OutputFormat::IndexedPng,
+            FormatChoice::Jpeg => OutputFormat::Jpeg(85),
+            FormatChoice::WebP => OutputFormat::WebP,
+        }
+    }
+}
+
+impl WallpaperSize {
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            WallpaperSize::FullHd => (1920, 1080),
+            WallpaperSize::Qhd => (2560, 1440),
+            WallpaperSize::Uhd => (3840, 2160),
+            WallpaperSize::Phone => (1080, 1920),
+        }
+    }
+}
+
+/// the built in palettes and the ones of palettes.toml, sorted by name
+async fn autocomplete_palette<'a>(ctx: Context<'a>, partial: &'a str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    ctx.data().palettes().names()
+        .filter(|name| name.starts_with(&partial))
+        .map(String::from)
+        .collect()
+}
+
+/// how far back /nordlast looks
+const RECENT_MESSAGES: u8 = 50;
+
+/// the newest message with an image attachment which isn't from a bot,
+/// `messages` are ordered newest first like discord returns them
+pub fn find_latest_image(messages: &[Message]) -> Option<&Message> {
+    messages.iter().find(|message| !message.author.bot && message.attachments.iter().any(is_image))
+}
+
+fn is_image(attachment: &Attachment) -> bool {
+    // a missing content type gets sniffed later, so it might still be an image
+    attachment.content_type.as_deref().map_or(true, |content_type| content_type.starts_with("image/"))
+}
+
+/// Convert the most recent image in this channel
+#[poise::command(slash_command)]
+pub async fn nordlast(ctx: Context<'_>) -> Result<(), AsyncError> {
+    let messages = ctx.channel_id().messages(ctx, GetMessages::new().limit(RECENT_MESSAGES)).await?;
+    let Some(message) = find_latest_image(&messages) else {
+        ctx.send(CreateReply::default().content("No recent image found").ephemeral(true)).await?;
+        return Ok(());
+    };
+    if ctx.data().rate_limiter.is_rate_limited(ctx.author().id, Instant::now()) {
+        ctx.send(CreateReply::default().content(ctx.data().messages(ctx.guild_id()).rate_limited).ephemeral(true)).await?;
+        return Ok(());
+    }
+    ctx.defer().await?;
+    let first_image = message.attachments.iter().find(|attachment| is_image(attachment)).unwrap();
+    let (_image, info) = fetch_image_and_info(first_image, ctx.data()).await?;
+    let mut options = NordOptions::from_image_information(&info);
+    options.start = true;
+    let _permit = ctx.data().queue.acquire().await;
+    let processed = process_attachments(message, ctx.guild_id(), ctx.data(), &options, &|_| {}).await?;
+    let mut response = CreateReply::default()
+        .content(format!(
+            "{}{}{}",
+            message.link(), processed.skipped_note(&ctx.data().messages(ctx.guild_id())), processed.downscaled_note(ctx.data().config().limits.max_dimension)
+        ))
+        .components(options.build_componets(u64::from(message.id), true));
+    for attachment in processed.files {
+        response = response.attachment(attachment);
+    }
+    ctx.send(response).await?;
+    Ok(())
+}
+
+/// discord rejects messages with more attachments
+const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
+
+/// splits `items` into groups of at most `size`, keeping their order
+pub fn into_batches<T>(items: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut batches: Vec<Vec<T>> = Vec::new();
+    for item in items {
+        match batches.last_mut() {
+            Some(batch) if batch.len() < size => batch.push(item),
+            _ => batches.push(vec![item]),
+        }
+    }
+    batches
+}
+
+/// Convert every image of the recent messages in this channel
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn nordbatch(
+    ctx: Context<'_>,
+    #[description = "How many recent messages to look through"]
+    #[min = 1]
+    #[max = 100]
+    limit: u8,
+) -> Result<(), AsyncError> {
+    ctx.defer().await?;
+    let data = ctx.data();
+    let messages = ctx.channel_id().messages(ctx, GetMessages::new().limit(limit)).await?;
+    let mut files = Vec::new();
+    let (mut total, mut converted) = (0, 0);
+    let mut rate_limited = false;
+    // oldest first, so the gallery keeps its order
+    for message in messages.iter().rev().filter(|message| !message.author.bot) {
+        let images = message.attachments.iter().filter(|attachment| is_image(attachment)).count();
+        if images == 0 {
+            continue;
+        }
+        total += images;
+        if rate_limited || data.rate_limiter.is_rate_limited(ctx.author().id, Instant::now()) {
+            rate_limited = true;
+            continue;
+        }
+        let first_image = message.attachments.iter().find(|attachment| is_image(attachment)).unwrap();
+        let Ok((_image, info)) = fetch_image_and_info(first_image, data).await else {
+            continue;
+        };
+        let mut options = NordOptions::from_image_information(&info);
+        options.start = true;
+        let _permit = data.queue.acquire().await;
+        // the skipped attachments of a message can be files which aren't images, so only the converted ones are counted
+        if let Ok(processed) = process_attachments(message, ctx.guild_id(), data, &options, &|_| {}).await {
+            converted += processed.converted;
+            files.extend(processed.files);
+        }
+    }
+    let skipped = total.saturating_sub(converted);
+    for batch in into_batches(files, MAX_ATTACHMENTS_PER_MESSAGE) {
+        ctx.channel_id().send_message(ctx, CreateMessage::new().add_files(batch)).await?;
+    }
+    let note = if rate_limited { ", I stopped early because of the rate limit" } else { "" };
+    ctx.say(format!("Converted {converted} of {total} images, {skipped} skipped{note}.")).await?;
+    Ok(())
+}
+
+/// Convert an attached or linked image, no matter how bright it is
+#[poise::command(slash_command)]
+pub async fn nord(
+    ctx: Context<'_>,
+    #[description = "Image to convert"] attachment: Option<Attachment>,
+    #[description = "Link to the image to convert"] url: Option<String>,
+    #[description = "How strong the conversion is, from 0.0 (original) to 1.0 (full)"]
+    #[min = 0.0]
+    #[max = 1.0]
+    strength: Option<f32>,
+    #[description = "Map the image onto the grey colors of the palette only"] grayscale: Option<bool>,
+    #[description = "Ordered dithering against banding, from 0.0 (off) to 1.0 (full)"]
+    #[min = 0.0]
+    #[max = 1.0]
+    dither: Option<f32>,
+    #[description = "Resize the result, from 0.25 to 2.0"]
+    #[min = 0.25]
+    #[max = 2.0]
+    scale: Option<f32>,
+    #[description = "Stretch the contrast before converting"] auto_contrast: Option<bool>,
+    #[description = "Boost the saturation before converting"] saturate: Option<bool>,
+    #[description = "Smooth out sensor noise of photos first, radius from 1 to 3"]
+    #[min = 1]
+    #[max = 3]
+    smooth: Option<u32>,
+    #[description = "Reduce every channel to this many levels first, from 2 to 8"]
+    #[min = 2]
+    #[max = 8]
+    posterize: Option<u16>,
+    #[description = "Only dim the image to the brightness threshold and keep its colors"] dim: Option<bool>,
+    #[description = "Repeat the result to fill a wallpaper"] tile: Option<WallpaperSize>,
+    #[description = "Palette to use instead of the one I pick, like dracula"]
+    #[autocomplete = "autocomplete_palette"]
+    palette: Option<String>,
+    #[description = "Only use these color groups, like polar_night,frost"] groups: Option<String>,
+    #[description = "Format of the result, the one of the attachment if left out"] format: Option<FormatChoice>,
+) -> Result<(), AsyncError> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id();
+    let texts = data.messages(guild_id);
+    let groups = match groups.as_deref().map(|groups| parse_color_groups(groups, &texts)) {
+        Some(Ok(groups)) => Some(groups),
+        Some(Err(e)) => {
+            ctx.send(CreateReply::default().content(e).ephemeral(true)).await?;
+            return Ok(());
+        }
+        None => None,
+    };
+    // built in palettes know which colors are backgrounds and accents, so they win over palettes.toml
+    let builtin = palette.as_deref()
+        .and_then(|name| name.to_lowercase().parse::<Palette>().ok());
+    let custom_colors = match builtin {
+        Some(Palette::Custom) => {
+            let saved = guild_id.is_some_and(|id| data.custom_palettes.lock().unwrap().contains_key(&id));
+            if !saved {
+                ctx.send(
+                    CreateReply::default()
+                        .content(&texts.no_custom_palette)
+                        .ephemeral(true)
+                ).await?;
+                return Ok(());
+            }
+            Some(data.palette_colors(Palette::Custom, guild_id))
+        }
+        _ => None,
+    };
+    let registered_colors = match palette.as_deref().filter(|_| builtin.is_none()) {
+        Some(name) => match data.palettes().get(name) {
+            Some(colors) => Some(PaletteColors::from_rgb(colors)),
+            None => {
+                let names: Vec<String> = data.palettes().names().map(String::from).collect();
+                ctx.send(
+                    CreateReply::default()
+                        .content(fill(&fill(&texts.unknown_palette, "name", name), "palettes", names.join(", ")))
+                        .ephemeral(true)
+                ).await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    let registered_name = palette.filter(|_| registered_colors.is_some());
+    let palette = builtin.unwrap_or(Palette::Nord);
+    let group_colors = match &groups {
+        // registered and custom palettes come without groups
+        Some(_) if registered_colors.is_some() || custom_colors.is_some() => {
+            ctx.send(CreateReply::default().content(&texts.groups_need_builtin_palette).ephemeral(true)).await?;
+            return Ok(());
+        }
+        Some(groups) => match PaletteColors::from_palette_groups(palette, groups) {
+            Some(colors) => Some(colors),
+            None => {
+                ctx.send(CreateReply::default().content(&texts.too_few_group_colors).ephemeral(true)).await?;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if data.rate_limiter.is_rate_limited(ctx.author().id, Instant::now()) {
+        ctx.send(CreateReply::default().content(&texts.rate_limited).ephemeral(true)).await?;
+        return Ok(());
+    }
+
+    let is_link = |url: &&str| url.starts_with("https://") || url.starts_with("http://");
+    let fetched = if let Some(attachment) = &attachment {
+        if let Err(e) = image_check(attachment).await {
+            ctx.send(CreateReply::default().content(fill(&texts.not_convertible, "error", e)).ephemeral(true)).await?;
+            return Ok(());
+        }
+        ctx.defer().await?;
+        fetch_image_and_info(attachment, data).await
+    } else if let Some(url) = url.as_deref().filter(is_link) {
+        ctx.defer().await?;
+        fetch_url_and_info(url, data).await
+    } else {
+        ctx.send(
+            CreateReply::default()
+                .content(&texts.no_image_given)
+                .ephemeral(true)
+        ).await?;
+        return Ok(());
+    };
+    let (image, info) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            ctx.say(fill(&texts.download_failed, "error", e)).await?;
+            return Ok(());
+        }
+    };
+
+    let mut options = NordOptions::from_image_information(&info);
+    options.start = true;
+    options.palette = palette;
+    options.strength = strength.unwrap_or(1.0).clamp(0.0, 1.0);
+    options.auto_contrast = auto_contrast.unwrap_or(false);
+    options.smooth = smooth.is_some();
+    options.dim = dim.unwrap_or(false);
+    options.format = match (format, &attachment) {
+        (Some(format), _) => format.output_format(),
+        (None, Some(attachment)) => OutputFormat::for_content_type(attachment.content_type.as_deref()),
+        (None, None) => OutputFormat::Png,
+    };
+    if posterize.is_some() {
+        // flat shading only shows once the levels are snapped to the palette
+        options.nord = true;
+    }
+    if let Some(dither) = dither.filter(|dither| *dither > 0.0) {
+        // dithering is part of the palette mapping as well
+        options.dither = Dither::Ordered(8, (dither * 100.0).round() as u8);
+        options.nord = true;
+    }
+    if grayscale.unwrap_or(false) {
+        // the grayscale mode is part of the palette mapping
+        options.grayscale = true;
+        options.nord = true;
+    }
+    if groups.is_some() {
+        // the groups only restrict the palette mapping, and only as long as nothing gets blended back in
+        options.nord = true;
+        options.snap = true;
+    }
+    // the palette which is actually applied
+    let applied = match (&groups, registered_name) {
+        (_, Some(name)) => Some(name),
+        (Some(groups), _) => Some(format!(
+            "{}:{}", palette.as_str(), groups.iter().map(ColorGroup::as_str).collect::<Vec<_>>().join("+")
+        )),
+        _ => None,
+    };
+    let extras = ConversionExtras {
+        colors: registered_colors.or(custom_colors).or(group_colors),
+        applied,
+        saturation: saturate.unwrap_or(false).then(|| data.config().contrast.saturation_boost),
+        smooth_radius: smooth.map(|radius| radius.clamp(1, 3)),
+        posterize: posterize.map(|levels| levels.clamp(colors::MIN_POSTERIZE_LEVELS, colors::MAX_POSTERIZE_LEVELS)),
+        scale,
+        tile: tile.map(|size| size.dimensions()),
+    };
+    let encode_options = EncodeOptions {
+        background: extras.palette_colors(&options, guild_id, data).background(),
+        png_compression: data.png_compression(guild_id),
+    };
+    let format = options.format;
+    let max_upload_bytes = (data.config().limits.max_upload_mib * 1024.0 * 1024.0) as usize;
+    let _permit = data.queue.acquire().await;
+    let processed = match &attachment {
+        Some(attachment) => {
+            // process_image picks the image up from the cache instead of downloading it again
+            data.image_cache.insert(attachment.url.clone(), (image, info)).await;
+            process_image(attachment, guild_id, data, options, extras, &|_| {}).await
+        }
+        None => convert_image(image, info, guild_id, data, options, extras, &|_| {}).await,
+    };
+    let encoded = match processed {
+        Ok(processed) => encode_processed(processed, format, encode_options, max_upload_bytes).await,
+        Err(e) => Err(e),
+    };
+    let (_stats, comparison, (buffer, extension)) = match encoded {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            ctx.say(fill(&texts.conversion_failed, "error", e)).await?;
+            return Ok(());
+        }
+    };
+    let mut response = CreateReply::default().attachment(CreateAttachment::bytes(buffer, format!("nord.{extension}")));
+    if let Some((buffer, format)) = comparison {
+        response = response.attachment(CreateAttachment::bytes(buffer, format!("nord-comparison.{}", format.extension())));
+    }
+    ctx.send(response).await?;
+    Ok(())
+}
+
+
+
+/// Set the brightness (0.0 - 1.0) above which I offer to darken images in this server
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_threshold(
+    ctx: Context<'_>,
+    #[description = "Average brightness from 0.0 (black) to 1.0 (white)"] value: f32,
+) -> Result<(), AsyncError> {
+    let value = match validate_threshold(value) {
+        Ok(value) => value,
+        Err(e) => {
+            ctx.send(CreateReply::default().content(e.to_string()).ephemeral(true)).await?;
+            return Ok(());
+        }
+    };
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data().guild_thresholds.lock().unwrap().insert(guild_id, value);
+    ctx.say(format!("I'll offer to darken images brighter than {value:.2} from now on.")).await?;
+    Ok(())
+}
+
+/// Set how hard I compress PNG results in this server
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_compression(
+    ctx: Context<'_>,
+    #[description = "Best gives the smallest files but takes longer"] compression: PngCompression,
+) -> Result<(), AsyncError> {
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data().guild_compression.lock().unwrap().insert(guild_id, compression);
+    ctx.say(format!("I'll compress PNG results with the {compression:?} setting from now on.")).await?;
+    Ok(())
+}
+
+/// Turn the automatic offer to darken bright images in this server on or off
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_auto(
+    ctx: Context<'_>,
+    #[description = "Off means I only convert images through commands"] enabled: bool,
+) -> Result<(), AsyncError> {
+    let guild_id = ctx.guild_id().unwrap();
+    let reply = if enabled {
+        ctx.data().auto_prompt_disabled.lock().unwrap().remove(&guild_id);
+        "I'll offer to darken bright images again."
+    } else {
+        ctx.data().auto_prompt_disabled.lock().unwrap().insert(guild_id);
+        "I won't offer to darken images anymore, the commands still work."
+    };
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// Pick the palette I offer when I ask to convert images in this server
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_palette(
+    ctx: Context<'_>,
+    #[description = "Light palettes are offered for dark images instead"] palette: Palette,
+) -> Result<(), AsyncError> {
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data().guild_palettes.lock().unwrap().insert(guild_id, palette);
+    let which = if palette.is_light() { "dark" } else { "bright" };
+    ctx.say(format!("I'll offer **{}** for {which} images from now on.", palette.name())).await?;
+    Ok(())
+}
+
+/// Show the brightness above which I offer to darken images
+#[poise::command(slash_command)]
+pub async fn threshold(ctx: Context<'_>) -> Result<(), AsyncError> {
+    let value = ctx.data().brightness_threshold(ctx.guild_id());
+    ctx.say(format!("I offer to darken images brighter than {value:.2}.")).await?;
+    Ok(())
+}
+
+
+
+/// Show the colors of a palette
+#[poise::command(slash_command)]
+pub async fn palette(
+    ctx: Context<'_>,
+    #[description = "Palette to show, Nord if empty"] palette: Option<Palette>,
+) -> Result<(), AsyncError> {
+    let palette = palette.unwrap_or(Palette::Nord);
+    let image = if palette == Palette::Custom {
+        let custom = ctx.guild_id()
+            .and_then(|id| ctx.data().custom_palettes.lock().unwrap().get(&id).cloned());
+        let Some(custom) = custom else {
+            ctx.send(
+                CreateReply::default()
+                    .content(ctx.data().messages(ctx.guild_id()).no_custom_palette)
+                    .ephemeral(true)
+            ).await?;
+            return Ok(());
+        };
+        let colors: Vec<RgbColor> = custom.iter().map(|&[r, g, b]| RgbColor::new(r, g, b)).collect();
+        render_color_swatches(&colors)
+    } else {
+        render_swatches(palette)
+    };
+    let buffer = encode_image(&image, OutputFormat::Png)?;
     ctx.send(
         CreateReply::default()
-            .attachment(CreateAttachment::bytes(buffer, &first_attachment.filename))
-            .components(options.build_componets(u64::from(message.id), true))
+            .content(format!("These are the colors of **{}**:", palette.name()))
+            .attachment(CreateAttachment::bytes(buffer, format!("{}.png", palette.as_str())))
     ).await?;
-    reply.delete(ctx).await?;
     Ok(())
 }
 
+/// Set the colors of the Custom palette for this server
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_custom_palette(
+    ctx: Context<'_>,
+    #[description = "3 to 32 hex colors separated by commas or spaces, like #2e3440, 88c0d0"] colors: String,
+) -> Result<(), AsyncError> {
+    let colors = match parse_hex_colors(&colors) {
+        Ok(colors) => colors,
+        Err(e) => {
+            ctx.send(CreateReply::default().content(e.to_string()).ephemeral(true)).await?;
+            return Ok(());
+        }
+    };
+    let guild_id = ctx.guild_id().unwrap();
+    let count = colors.len();
+    ctx.data().custom_palettes.lock().unwrap().insert(guild_id, colors);
+    // the server wants its own colors, so the prompts offer them from now on
+    ctx.data().guild_palettes.lock().unwrap().insert(guild_id, Palette::Custom);
+    ctx.say(format!("Saved {count} colors, I'll offer the **Custom** palette from now on.")).await?;
+    Ok(())
+}
+
+/// Delete the Custom palette of this server and go back to Nord
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn reset_custom_palette(ctx: Context<'_>) -> Result<(), AsyncError> {
+    let guild_id = ctx.guild_id().unwrap();
+    let removed = ctx.data().custom_palettes.lock().unwrap().remove(&guild_id).is_some();
+    ctx.data().guild_palettes.lock().unwrap().remove(&guild_id);
+    let reply = if removed { "Deleted the custom palette, I'm back to Nord." } else { "There is no custom palette, I'm using Nord." };
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// Show how many images I have converted since my last restart
+#[poise::command(slash_command)]
+pub async fn stats(ctx: Context<'_>) -> Result<(), AsyncError> {
+    let (total, per_palette) = ctx.data().conversions.totals();
+    let per_palette = per_palette
+        .iter()
+        .map(|(palette, count)| {
+            // registered palettes and color groups are counted under their own id
+            let name = palette.parse::<Palette>().map_or(palette.as_str(), |palette| palette.name());
+            format!("{name}: {}", format_count(*count))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let breakdown = if per_palette.is_empty() { String::new() } else { format!(" ({per_palette})") };
+    let (hits, misses) = ctx.data().result_cache.hits_and_misses();
+    ctx.say(format!(
+        "{} images converted{breakdown}\nResult cache: {} hits, {} misses",
+        format_count(total), format_count(hits), format_count(misses)
+    )).await?;
+    Ok(())
+}
+
+/// Set the language and tone of my messages in this server
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_GUILD")]
+pub async fn set_locale(
+    ctx: Context<'_>,
+    #[description = "Name of the locale, like en"] locale: String,
+) -> Result<(), AsyncError> {
+    let locales = ctx.data().locales();
+    if !locales.contains_key(&locale) {
+        let mut available: Vec<&str> = locales.keys().map(String::as_str).collect();
+        available.sort();
+        ctx.send(
+            CreateReply::default()
+                .content(format!("I don't know `{locale}`. Pick one of: {}", available.join(", ")))
+                .ephemeral(true)
+        ).await?;
+        return Ok(());
+    }
+    let guild_id = ctx.guild_id().unwrap();
+    ctx.data().guild_locales.lock().unwrap().insert(guild_id, locale.clone());
+    ctx.say(format!("I'll use `{locale}` from now on.")).await?;
+    Ok(())
+}
+
+/// Read config.toml and the locales again without restarting
+#[poise::command(slash_command, owners_only)]
+pub async fn reload(ctx: Context<'_>) -> Result<(), AsyncError> {
+    let path = config::config_path();
+    let reply = match config::read_config(&path) {
+        Ok(config) => {
+            let config = config::with_env_overrides(config);
+            let locales = messages::load_locales(&messages::locales_dir());
+            let locale_count = locales.len();
+            let palettes = PaletteRegistry::load();
+            let palette_count = palettes.names().count();
+            ctx.data().reload(config, locales, palettes);
+            format!("Reloaded `{}`, {locale_count} locales and {palette_count} palettes.", path.display())
+        }
+        Err(error) => format!("Keeping the old config, `{}` is broken: {error}", path.display()),
+    };
+    ctx.send(CreateReply::default().content(reply).ephemeral(true)).await?;
+    Ok(())
+}
+
+/// Show how far an image is from the colors of a palette under every distance metric
+#[poise::command(slash_command, owners_only)]
+pub async fn analyze(
+    ctx: Context<'_>,
+    #[description = "Image to analyze"] attachment: Attachment,
+    #[description = "Palette to compare against, Nord if empty"] palette: Option<Palette>,
+) -> Result<(), AsyncError> {
+    let palette = palette.unwrap_or(Palette::Nord);
+    ctx.defer_ephemeral().await?;
+    let (image, _info) = fetch_image_and_info(&attachment, ctx.data()).await?;
+    let lines: Vec<String> = DistanceMetric::iter()
+        .into_iter()
+        .map(|metric| {
+            let (mean, max) = colors::analyze_conversion(&image, palette, metric);
+            format!("{:<13} mean {mean:>8.3}  max {max:>8.3}", metric.as_str())
+        })
+        .collect();
+    ctx.say(format!("Distances to **{}**:\n```\n{}\n```", palette.name(), lines.join("\n"))).await?;
+    Ok(())
+}
+
+/// parses a comma separated list like "polar_night, frost"
+fn parse_color_groups(value: &str, texts: &Messages) -> Result<Vec<ColorGroup>, String> {
+    let names: Vec<String> = ColorGroup::ALL.iter().map(|group| format!("`{}`", group.as_str())).collect();
+    let groups = value
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.parse::<ColorGroup>()
+            .map_err(|_| fill(&fill(&texts.unknown_color_group, "name", &name), "groups", names.join(", "))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if groups.is_empty() {
+        return Err(fill(&texts.no_color_group, "groups", names.join(", ")));
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::MessageId;
+
+    fn attachment(filename: &str, content_type: Option<&str>) -> Attachment {
+        let content_type = content_type.map_or(String::new(), |content_type| format!(r#", "content_type": "{content_type}""#));
+        serenity::json::from_str(&format!(
+            r#"{{"id": "1", "filename": "{filename}", "size": 1024, "url": "https://cdn.example/{filename}",
+                "proxy_url": "https://media.example/{filename}"{content_type}}}"#
+        )).unwrap()
+    }
+
+    fn message(id: u64, bot: bool, attachments: Vec<Attachment>) -> Message {
+        let mut message = Message::default();
+        message.id = MessageId::new(id);
+        message.author.bot = bot;
+        message.attachments = attachments;
+        message
+    }
+
+    #[test]
+    fn finds_the_newest_image_of_a_user() {
+        let messages = vec![
+            message(6, false, Vec::new()),
+            message(5, true, vec![attachment("converted.webp", Some("image/webp"))]),
+            message(4, false, vec![attachment("notes.txt", Some("text/plain"))]),
+            message(3, false, vec![attachment("notes.txt", Some("text/plain")), attachment("cat.png", Some("image/png"))]),
+            message(2, false, vec![attachment("dog.png", Some("image/png"))]),
+        ];
+        assert_eq!(find_latest_image(&messages).map(|message| message.id.get()), Some(3));
+
+        // without a content type the bytes decide later
+        let messages = vec![message(2, false, vec![attachment("unknown", None)])];
+        assert_eq!(find_latest_image(&messages).map(|message| message.id.get()), Some(2));
+
+        let messages = vec![message(2, true, vec![attachment("cat.png", Some("image/png"))]), message(1, false, Vec::new())];
+        assert!(find_latest_image(&messages).is_none());
+    }
+
+    #[test]
+    fn batches_hold_at_most_ten_files() {
+        let batches = into_batches((1..=34).collect(), MAX_ATTACHMENTS_PER_MESSAGE);
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![10, 10, 10, 4]);
+        assert_eq!(batches.concat(), (1..=34).collect::<Vec<_>>());
+
+        assert_eq!(into_batches((1..=10).collect::<Vec<_>>(), 10).len(), 1);
+        assert!(into_batches(Vec::<u8>::new(), 10).is_empty());
+    }
+
+    #[test]
+    fn color_groups_are_parsed_from_a_list() {
+        let texts = Messages::default();
+        assert_eq!(parse_color_groups("polar_night,frost", &texts), Ok(vec![ColorGroup::PolarNight, ColorGroup::Frost]));
+        assert_eq!(parse_color_groups(" Snow_Storm , aurora,", &texts), Ok(vec![ColorGroup::SnowStorm, ColorGroup::Aurora]));
+        assert!(parse_color_groups("polar_night,purple", &texts).unwrap_err().contains("`purple`"));
+        assert!(parse_color_groups(" , ", &texts).is_err());
+    }
+
+    #[test]
+    fn groups_with_fewer_than_two_colors_are_rejected() {
+        assert!(PaletteColors::from_palette_groups(Palette::Nord, &[]).is_none());
+        let frost = PaletteColors::from_palette_groups(Palette::Nord, &[ColorGroup::Frost]).unwrap();
+        assert_eq!(frost.darks, Palette::Nord.accents());
+    }
+}
@@ -0,0 +1,85 @@
+use image::{imageops::{self, FilterType}, DynamicImage, GenericImageView, GrayImage, Luma, RgbaImage};
+
+use crate::colors::Palette;
+
+
+const DIVIDER_WIDTH: u32 = 6;
+
+/// puts the original on the left and the conversion on the right, both with the same height
+pub fn make_comparison(before: &DynamicImage, after: &DynamicImage, palette: Palette) -> DynamicImage {
+    let height = before.height().max(after.height()).max(1);
+    let scale_to_height = |image: &DynamicImage| {
+        let width = ((image.width() as f32 * height as f32 / image.height().max(1) as f32).round() as u32).max(1);
+        image.resize_exact(width, height, FilterType::Lanczos3).to_rgba8()
+    };
+    let (before, after) = (scale_to_height(before), scale_to_height(after));
+
+    // the custom palette has no colors of its own
+    let accents = match palette.accents() {
+        [] => Palette::Nord.accents(),
+        accents => accents,
+    };
+    let divider = accents[0].to_rgba();
+
+    let width = before.width() + DIVIDER_WIDTH + after.width();
+    let mut comparison = RgbaImage::from_pixel(width, height, divider);
+    imageops::replace(&mut comparison, &before, 0, 0);
+    imageops::replace(&mut comparison, &after, (before.width() + DIVIDER_WIDTH) as i64, 0);
+    DynamicImage::ImageRgba8(comparison)
+}
+
+/// heatmap of how far every pixel moved, untouched pixels stay black and the biggest changes glow white.
+/// `after` is scaled to the size of `before` if they differ
+pub fn render_diff(before: &DynamicImage, after: &DynamicImage) -> DynamicImage {
+    // distance between black and white
+    let max_distance = (3.0f32 * 255.0 * 255.0).sqrt();
+    let before = before.to_rgba8();
+    let after = if after.dimensions() == before.dimensions() {
+        after.to_rgba8()
+    } else {
+        after.resize_exact(before.width(), before.height(), FilterType::Nearest).to_rgba8()
+    };
+    let diff = GrayImage::from_fn(before.width(), before.height(), |x, y| {
+        let (b, a) = (before.get_pixel(x, y), after.get_pixel(x, y));
+        let distance = (0..3)
+            .map(|c| (b[c] as f32 - a[c] as f32).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        Luma([(distance / max_distance * 255.0).round() as u8])
+    });
+    DynamicImage::ImageLuma8(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_sit_next_to_each_other() {
+        let before = DynamicImage::new_rgba8(200, 100);
+        let after = DynamicImage::new_rgba8(100, 50);
+        let comparison = make_comparison(&before, &after, Palette::Nord);
+        // the smaller half is scaled up to the same height
+        assert_eq!(comparison.dimensions(), (200 + DIVIDER_WIDTH + 200, 100));
+        assert_eq!(comparison.get_pixel(200, 50), Palette::Nord.accents()[0].to_rgba());
+    }
+
+    #[test]
+    fn diff_glows_where_pixels_changed() {
+        let before = DynamicImage::ImageRgba8(RgbaImage::from_pixel(20, 10, image::Rgba([255, 255, 255, 255])));
+        let unchanged = render_diff(&before, &before).to_luma8();
+        assert!(unchanged.pixels().all(|Luma([value])| *value == 0));
+
+        // the right half turned black
+        let mut after = before.to_rgba8();
+        for x in 10..20 {
+            for y in 0..10 {
+                after.put_pixel(x, y, image::Rgba([0, 0, 0, 255]));
+            }
+        }
+        let diff = render_diff(&before, &DynamicImage::ImageRgba8(after)).to_luma8();
+        assert_eq!(diff.dimensions(), (20, 10));
+        assert_eq!(diff.get_pixel(5, 5)[0], 0);
+        assert_eq!(diff.get_pixel(15, 5)[0], 255);
+    }
+}
@@ -1,10 +1,30 @@
+use std::path::{Path, PathBuf};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use toml;
 
+use crate::encode::PngCompression;
+
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     pub threshold: ThresholdConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub contrast: ContrastConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub gradient: GradientConfig,
+    #[serde(default)]
+    pub animation: AnimationConfig,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -13,6 +33,121 @@ pub struct ThresholdConfig {
     pub modelpath: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// longer sides get downscaled to this before converting
+    pub max_dimension: u32,
+    /// conversions running at the same time, everyone else waits in line
+    pub max_concurrent_conversions: usize,
+    /// bigger images are rejected before they get decoded
+    pub max_megapixels: f32,
+    /// files above this are encoded again with lower quality or size, Discord rejects bigger uploads
+    pub max_upload_mib: f32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { max_dimension: 2048, max_concurrent_conversions: 2, max_megapixels: 40.0, max_upload_mib: 8.0 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct ContrastConfig {
+    /// percent of the darkest and lightest pixels which get clipped by auto contrast
+    pub clip_percent: f32,
+    /// factor of the saturation boost, 1.0 keeps the colors
+    pub saturation_boost: f32,
+}
+
+impl Default for ContrastConfig {
+    fn default() -> Self {
+        ContrastConfig { clip_percent: 1.0, saturation_boost: 1.3 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct PromptConfig {
+    /// the darken button of an unanswered prompt gets disabled after this
+    pub expire_secs: u64,
+    /// images darker than this get offered Nord Light, 0.0 turns that off
+    pub lighten_below: f32,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        PromptConfig { expire_secs: 600, lighten_below: 0.2 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HealthConfig {
+    /// port of /healthz and /metrics
+    pub port: u16,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig { port: 8000 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GradientConfig {
+    /// indices into the colors of the palette (darks, lights, accents, highlights) the gradient map runs through
+    pub ramp: Vec<usize>,
+}
+
+impl Default for GradientConfig {
+    fn default() -> Self {
+        // nord0, nord3, nord9, nord4 and nord6 for Nord
+        GradientConfig { ramp: vec![0, 3, 9, 4, 6] }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct AnimationConfig {
+    /// longer animations are rejected, unless skip_frames is on
+    pub max_frames: usize,
+    /// frames get downscaled to fit into max_width x max_height
+    pub max_width: u32,
+    pub max_height: u32,
+    /// keeps every nth frame of long animations instead of rejecting them
+    pub skip_frames: bool,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        AnimationConfig { max_frames: 100, max_width: 1280, max_height: 720, skip_frames: true }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// servers can pick their own with /set_compression
+    pub png_compression: PngCompression,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// how many converted images are kept
+    pub result_capacity: usize,
+    pub result_ttl_secs: u64,
+    /// upper bound for the size of all cached files together
+    pub result_max_mib: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { result_capacity: 50, result_ttl_secs: 600, result_max_mib: 64 }
+    }
+}
+
 pub fn load_config() -> Config {
     // Include the contents of config.toml at compile time
     // pwd:
@@ -23,4 +158,105 @@ pub fn load_config() -> Config {
     let config: Config = toml::from_str(config_str).expect("Failed to parse `config.toml` in root dir (where Cargo.toml is located)");
 
     config
-}
\ No newline at end of file
+}
+
+/// config.toml next to the binary, or CONFIG_PATH
+pub fn config_path() -> PathBuf {
+    PathBuf::from(std::env::var("CONFIG_PATH").unwrap_or_else(|_| String::from("config.toml")))
+}
+
+/// reads the config from disk, so it can change without building again
+pub fn read_config(path: &Path) -> anyhow::Result<Config> {
+    let config_str = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&config_str)?)
+}
+
+/// BRIGHTNESS_THRESHOLD replaces the threshold of the file, so a deployment can tune it without editing config.toml
+pub fn with_env_overrides(mut config: Config) -> Config {
+    if let Ok(value) = std::env::var("BRIGHTNESS_THRESHOLD") {
+        match value.parse::<f32>().map_err(anyhow::Error::from).and_then(validate_threshold) {
+            Ok(threshold) => config.threshold.brightness = threshold,
+            Err(e) => warn!("Ignoring BRIGHTNESS_THRESHOLD={} reason={}", value, e),
+        }
+    }
+    config
+}
+
+/// brightness thresholds are compared against the average brightness (0.0 - 1.0)
+pub fn validate_threshold(value: f32) -> anyhow::Result<f32> {
+    if !(0.0..=1.0).contains(&value) {
+        anyhow::bail!("The threshold has to be between 0.0 and 1.0, not {value}");
+    }
+    Ok(value)
+}
+
+/// parses a comma or whitespace separated list like `#2e3440, #88c0d0 bf616a`, `#rgb` is expanded to `#rrggbb`
+/// fewer colors can't tell backgrounds from accents, more are slow to match against
+pub const MIN_CUSTOM_COLORS: usize = 3;
+pub const MAX_CUSTOM_COLORS: usize = 32;
+
+pub fn parse_hex_colors(input: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let colors = input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|code| !code.is_empty())
+        .map(parse_hex_color)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if colors.len() < MIN_CUSTOM_COLORS {
+        anyhow::bail!("I need at least {MIN_CUSTOM_COLORS} colors like #88c0d0, you gave me {}", colors.len());
+    }
+    if colors.len() > MAX_CUSTOM_COLORS {
+        anyhow::bail!("I take up to {MAX_CUSTOM_COLORS} colors, you gave me {}", colors.len());
+    }
+    Ok(colors)
+}
+
+pub fn parse_hex_color(code: &str) -> anyhow::Result<[u8; 3]> {
+    let digits = code.strip_prefix('#').unwrap_or(code);
+    let digits: String = match digits.len() {
+        3 => digits.chars().flat_map(|c| [c, c]).collect(),
+        6 => digits.to_string(),
+        _ => anyhow::bail!("`{code}` is not a hex color like #88c0d0 or #8cd"),
+    };
+    let channel = |i: usize| {
+        u8::from_str_radix(&digits[i..i + 2], 16)
+            .map_err(|_| anyhow::anyhow!("`{code}` is not a hex color like #88c0d0 or #8cd"))
+    };
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threshold_has_to_be_a_share() {
+        assert_eq!(validate_threshold(0.0).unwrap(), 0.0);
+        assert_eq!(validate_threshold(0.4).unwrap(), 0.4);
+        assert_eq!(validate_threshold(1.0).unwrap(), 1.0);
+        assert!(validate_threshold(-0.1).is_err());
+        assert!(validate_threshold(1.01).is_err());
+        assert!(validate_threshold(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn parses_hex_color_lists() {
+        assert_eq!(
+            parse_hex_colors("#2e3440, #88c0d0 bf616a").unwrap(),
+            vec![[0x2e, 0x34, 0x40], [0x88, 0xc0, 0xd0], [0xbf, 0x61, 0x6a]]
+        );
+        assert_eq!(
+            parse_hex_colors("#8cd,#fff,  000").unwrap(),
+            vec![[0x88, 0xcc, 0xdd], [0xff, 0xff, 0xff], [0x00, 0x00, 0x00]]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hex_colors() {
+        assert!(parse_hex_colors("").is_err());
+        assert!(parse_hex_colors(" , ").is_err());
+        assert!(parse_hex_colors("#2e3440, #88c0d0").is_err());
+        assert!(parse_hex_colors("#2e3440, #88c0d0, #bf616").is_err());
+        assert!(parse_hex_colors("#2e3440, #88c0d0, #gggggg").is_err());
+        assert!(parse_hex_colors(&vec!["#fff"; MAX_CUSTOM_COLORS + 1].join(",")).is_err());
+    }
+}
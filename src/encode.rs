@@ -0,0 +1,394 @@
+use std::{collections::HashMap, io::Cursor, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::{CompressionType, FilterType, PngEncoder}},
+    imageops::FilterType as ResizeFilter, DynamicImage, ImageFormat, Rgba, RgbImage
+};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+
+// nord0, used to fill transparent areas of formats without alpha
+pub const BACKGROUND: [u8; 3] = [46, 52, 64];
+
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    /// PNG with at most 256 colors stored as indices into its PLTE chunk
+    IndexedPng,
+    Jpeg(u8),
+    WebP,
+}
+
+impl OutputFormat {
+    /// key used in custom ids
+    pub fn as_str(&self) -> String {
+        match self {
+            OutputFormat::Png => String::from("png"),
+            OutputFormat::IndexedPng => String::from("png8"),
+            OutputFormat::Jpeg(quality) => format!("jpeg{quality}"),
+            OutputFormat::WebP => String::from("webp"),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "PNG",
+            OutputFormat::IndexedPng => "Indexed PNG",
+            OutputFormat::Jpeg(_) => "JPEG",
+            OutputFormat::WebP => "WebP",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::IndexedPng => "png",
+            OutputFormat::Jpeg(_) => "jpg",
+            OutputFormat::WebP => "webp",
+        }
+    }
+
+    /// JPEGs stay JPEGs, everything else becomes a lossless PNG, which every client can show
+    pub fn for_content_type(content_type: Option<&str>) -> Self {
+        match content_type {
+            Some("image/jpeg") => OutputFormat::Jpeg(85),
+            _ => OutputFormat::Png,
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            OutputFormat::WebP => OutputFormat::Png,
+            OutputFormat::Png => OutputFormat::IndexedPng,
+            OutputFormat::IndexedPng => OutputFormat::Jpeg(85),
+            OutputFormat::Jpeg(_) => OutputFormat::WebP,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    /// parses the keys of custom ids
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "png" => Ok(OutputFormat::Png),
+            "png8" => Ok(OutputFormat::IndexedPng),
+            "webp" => Ok(OutputFormat::WebP),
+            _ => {
+                let quality = value.strip_prefix("jpeg")
+                    .and_then(|quality| quality.parse::<u8>().ok())
+                    .with_context(|| format!("Unknown output format: {value}"))?;
+                Ok(OutputFormat::Jpeg(quality.clamp(1, 100)))
+            }
+        }
+    }
+}
+
+/// zlib effort of PNG output, better compression takes longer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, poise::ChoiceParameter)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    fn to_compression_type(self) -> CompressionType {
+        match self {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+
+    fn to_png_compression(self) -> png::Compression {
+        match self {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/// settings of the output file which don't change the pixels
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeOptions {
+    /// transparent areas are flattened onto this for formats without alpha
+    pub background: [u8; 3],
+    pub png_compression: PngCompression,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions { background: BACKGROUND, png_compression: PngCompression::Default }
+    }
+}
+
+/// encodes with the default options, transparent areas of JPEGs become the Nord background
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage, Rgba};
+/// use image2nord::encode::{encode_image, OutputFormat};
+///
+/// let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([46, 52, 64, 255])));
+/// let png = encode_image(&image, OutputFormat::Png).unwrap();
+/// assert_eq!(image::load_from_memory(&png).unwrap().width(), 4);
+/// ```
+pub fn encode_image(image: &DynamicImage, format: OutputFormat) -> Result<Vec<u8>> {
+    encode_image_with(image, format, EncodeOptions::default())
+}
+
+/// the encoders only write pixels, EXIF and ICC data of the original never end up in the output
+pub fn encode_image_with(image: &DynamicImage, format: OutputFormat, options: EncodeOptions) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    match format {
+        OutputFormat::IndexedPng => {
+            if let Some(indexed) = encode_indexed_png(image, options.png_compression)? {
+                return Ok(indexed);
+            }
+            // blending and dithering can leave more colors than a PLTE chunk holds
+            info!("Too many colors for an indexed PNG, writing truecolor instead");
+            return encode_image_with(image, OutputFormat::Png, options);
+        }
+        OutputFormat::Png => {
+            let encoder = PngEncoder::new_with_quality(&mut buffer, options.png_compression.to_compression_type(), FilterType::Adaptive);
+            image.write_with_encoder(encoder)?;
+        }
+        // the webp encoder only takes 8 bit rgb(a)
+        OutputFormat::WebP => DynamicImage::from(image.to_rgba8()).write_to(&mut buffer, ImageFormat::WebP)?,
+        OutputFormat::Jpeg(quality) => {
+            let encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
+            flatten_alpha(image, options.background).write_with_encoder(encoder)?;
+        }
+    }
+    Ok(buffer.into_inner())
+}
+
+/// encodes the image and falls back to lower JPEG qualities and then smaller sizes
+/// until the file has at most max_bytes, returns the format which was used in the end
+pub fn encode_within_budget(image: &DynamicImage, format: OutputFormat, options: EncodeOptions, max_bytes: usize) -> Result<(Vec<u8>, OutputFormat)> {
+    const QUALITIES: [u8; 4] = [85, 70, 55, 40];
+    const SHRINK_STEPS: u32 = 4;
+    let buffer = encode_image_with(image, format, options)?;
+    if buffer.len() <= max_bytes {
+        return Ok((buffer, format));
+    }
+    debug!("Encoded file too big bytes={} max_bytes={max_bytes} format={}", buffer.len(), format.name());
+    let start_quality = match format {
+        OutputFormat::Jpeg(quality) => quality,
+        _ => u8::MAX,
+    };
+    for quality in QUALITIES.into_iter().filter(|quality| *quality < start_quality) {
+        let buffer = encode_image_with(image, OutputFormat::Jpeg(quality), options)?;
+        if buffer.len() <= max_bytes {
+            return Ok((buffer, OutputFormat::Jpeg(quality)));
+        }
+    }
+    let lowest = OutputFormat::Jpeg(QUALITIES[QUALITIES.len() - 1]);
+    let (mut width, mut height) = (image.width(), image.height());
+    for _ in 0..SHRINK_STEPS {
+        (width, height) = ((width * 3 / 4).max(1), (height * 3 / 4).max(1));
+        let smaller = image.resize(width, height, ResizeFilter::Lanczos3);
+        let buffer = encode_image_with(&smaller, lowest, options)?;
+        if buffer.len() <= max_bytes {
+            debug!("Shrunk output to fit width={width} height={height}");
+            return Ok((buffer, lowest));
+        }
+    }
+    bail!("The result is too big to upload, even at low quality. Try a smaller image.")
+}
+
+/// None if the image has more than 256 distinct colors, alpha included
+fn encode_indexed_png(image: &DynamicImage, compression: PngCompression) -> Result<Option<Vec<u8>>> {
+    let rgba = image.to_rgba8();
+    let mut entries: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.width() as usize * rgba.height() as usize);
+    for Rgba(pixel) in rgba.pixels() {
+        let index = match entries.get(pixel) {
+            Some(index) => *index,
+            None => {
+                if palette.len() == 256 {
+                    return Ok(None);
+                }
+                let index = palette.len() as u8;
+                entries.insert(*pixel, index);
+                palette.push(*pixel);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, rgba.width(), rgba.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(compression.to_png_compression());
+    encoder.set_palette(palette.iter().flat_map(|[r, g, b, _]| [*r, *g, *b]).collect::<Vec<u8>>());
+    if palette.iter().any(|[_, _, _, a]| *a < 255) {
+        encoder.set_trns(palette.iter().map(|[_, _, _, a]| *a).collect::<Vec<u8>>());
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&indices)?;
+    writer.finish()?;
+    debug!("Encoded indexed PNG colors={} bytes={}", palette.len(), buffer.len());
+    Ok(Some(buffer))
+}
+
+/// composites the image over a solid background color
+pub fn flatten_alpha(image: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let rgba = image.to_rgba8();
+    let mut flattened = RgbImage::new(rgba.width(), rgba.height());
+    for (x, y, Rgba([r, g, b, a])) in rgba.enumerate_pixels() {
+        let alpha = *a as f32 / 255.0;
+        let blend = |channel: u8, background: u8| {
+            (channel as f32 * alpha + background as f32 * (1.0 - alpha)).round() as u8
+        };
+        flattened.put_pixel(x, y, image::Rgb([
+            blend(*r, background[0]),
+            blend(*g, background[1]),
+            blend(*b, background[2]),
+        ]));
+    }
+    DynamicImage::ImageRgb8(flattened)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    /// a small image with a gradient and a half transparent corner
+    fn sample_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 12, |x, y| {
+            let alpha = if x < 4 && y < 4 { 128 } else { 255 };
+            Rgba([(x * 16) as u8, (y * 20) as u8, 180, alpha])
+        }))
+    }
+
+    #[test]
+    fn every_format_decodes_again() {
+        let image = sample_image();
+        for format in [OutputFormat::Png, OutputFormat::IndexedPng, OutputFormat::Jpeg(85), OutputFormat::WebP] {
+            let buffer = encode_image(&image, format).unwrap();
+            let decoded = image::load_from_memory(&buffer).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (16, 12), "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn jpeg_flattens_alpha() {
+        let buffer = encode_image(&sample_image(), OutputFormat::Jpeg(100)).unwrap();
+        let decoded = image::load_from_memory_with_format(&buffer, ImageFormat::Jpeg).unwrap();
+        assert!(!decoded.color().has_alpha());
+    }
+
+    #[test]
+    fn half_transparent_white_blends_into_the_background() {
+        let mut image = RgbaImage::from_pixel(3, 1, Rgba([255, 255, 255, 128]));
+        image.put_pixel(1, 0, Rgba([136, 192, 208, 255]));
+        image.put_pixel(2, 0, Rgba([255, 0, 0, 0]));
+        let flattened = flatten_alpha(&DynamicImage::ImageRgba8(image), [46, 52, 64]).to_rgb8();
+        // 255 * 128/255 + background * 127/255
+        assert_eq!(flattened.get_pixel(0, 0).0, [151, 154, 160]);
+        assert_eq!(flattened.get_pixel(1, 0).0, [136, 192, 208]);
+        assert_eq!(flattened.get_pixel(2, 0).0, [46, 52, 64]);
+    }
+
+    #[test]
+    fn better_compression_is_not_bigger() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(256, 256, |x, y| {
+            Rgba([x as u8, y as u8, ((x + y) / 2) as u8, 255])
+        }));
+        let png = |png_compression| {
+            encode_image_with(&image, OutputFormat::Png, EncodeOptions { png_compression, ..EncodeOptions::default() }).unwrap().len()
+        };
+        assert!(png(PngCompression::Best) <= png(PngCompression::Fast));
+        let jpeg = |quality| encode_image(&image, OutputFormat::Jpeg(quality)).unwrap().len();
+        assert!(jpeg(40) <= jpeg(95));
+    }
+
+    #[test]
+    fn output_carries_no_exif() {
+        let image = sample_image();
+        for format in [OutputFormat::Png, OutputFormat::IndexedPng, OutputFormat::Jpeg(85), OutputFormat::WebP] {
+            let buffer = encode_image(&image, format).unwrap();
+            assert!(!buffer.windows(6).any(|window| window == b"Exif\0\0"), "{}", format.name());
+            assert!(!buffer.windows(4).any(|window| window == b"eXIf" || window == b"EXIF"), "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn indexed_png_keeps_palette_images_and_is_smaller() {
+        let palette = [[46, 52, 64, 255], [136, 192, 208, 255], [191, 97, 106, 255], [235, 203, 139, 128], [0, 0, 0, 0]];
+        let mut seed = 7u32;
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(128, 128, |_, _| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            Rgba(palette[(seed >> 24) as usize % palette.len()])
+        }));
+        let indexed = encode_image(&image, OutputFormat::IndexedPng).unwrap();
+        let truecolor = encode_image(&image, OutputFormat::Png).unwrap();
+        assert_eq!(image::load_from_memory(&indexed).unwrap().to_rgba8(), image.to_rgba8());
+        assert!(indexed.len() < truecolor.len(), "{} >= {}", indexed.len(), truecolor.len());
+    }
+
+    #[test]
+    fn too_many_colors_fall_back_to_truecolor() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 0, 255])));
+        let buffer = encode_image(&image, OutputFormat::IndexedPng).unwrap();
+        assert_eq!(image::load_from_memory(&buffer).unwrap().to_rgba8(), image.to_rgba8());
+    }
+
+    /// a JPEG with an EXIF segment whose GPS IFD says where the photo was taken
+    fn jpeg_with_gps() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&sample_image().to_rgb8()).unwrap();
+
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        // IFD0 with only the pointer to the GPS IFD right behind it
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&[0x88, 0x25, 0x00, 0x04, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&26u32.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        // GPS IFD with the latitude reference "N"
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&[0x00, 0x01, 0x00, 0x02, 0, 0, 0, 2, b'N', 0, 0, 0]);
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend_from_slice(&tiff);
+
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(&segment);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn gps_data_of_the_input_is_not_encoded() {
+        let input = jpeg_with_gps();
+        assert!(input.windows(6).any(|window| window == b"Exif\0\0"));
+        let image = image::load_from_memory(&input).unwrap();
+        for format in [OutputFormat::Png, OutputFormat::IndexedPng, OutputFormat::Jpeg(85), OutputFormat::WebP] {
+            let buffer = encode_image(&image, format).unwrap();
+            assert!(!buffer.windows(6).any(|window| window == b"Exif\0\0"), "{}", format.name());
+        }
+        // entropy coded data escapes 0xFF, so this can only be an APP1 marker
+        let jpeg = encode_image(&image, OutputFormat::Jpeg(85)).unwrap();
+        assert!(!jpeg.windows(2).any(|window| window == [0xFF, 0xE1]));
+    }
+
+    #[test]
+    fn only_jpegs_stay_lossy() {
+        assert_eq!(OutputFormat::for_content_type(Some("image/jpeg")), OutputFormat::Jpeg(85));
+        for content_type in [Some("image/png"), Some("image/webp"), Some("image/gif"), None] {
+            assert_eq!(OutputFormat::for_content_type(content_type), OutputFormat::Png, "{content_type:?}");
+        }
+    }
+}
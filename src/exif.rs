@@ -0,0 +1,155 @@
+use image::DynamicImage;
+
+
+/// reads the orientation tag (1 - 8) from the EXIF segment of a JPEG or the first IFD of a TIFF
+pub fn read_orientation(bytes: &[u8]) -> Option<u16> {
+    // TIFFs are their own EXIF data
+    if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        return read_tiff_orientation(bytes);
+    }
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    // walk the markers until the APP1 segment carrying the EXIF data
+    let mut i = 2;
+    while i + 4 <= bytes.len() {
+        if bytes[i] != 0xFF {
+            return None;
+        }
+        let marker = bytes[i + 1];
+        let length = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        // start of scan, no more metadata after this
+        if marker == 0xDA {
+            return None;
+        }
+        let segment = bytes.get(i + 4..i + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return read_tiff_orientation(&segment[6..]);
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+fn read_tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let b = tiff.get(offset..offset + 2)?;
+        Some(if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |offset: usize| {
+        let b = tiff.get(offset..offset + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    };
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    for n in 0..entries {
+        let entry = ifd + 2 + n * 12;
+        // 0x0112 is the orientation tag, a single SHORT stored inline
+        if u16_at(entry)? == 0x0112 {
+            return u16_at(entry + 8).filter(|value| (1..=8).contains(value));
+        }
+    }
+    None
+}
+
+/// rotates and flips the image so that it is upright, like discord renders it
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+    /// big endian TIFF header with a single IFD holding only the orientation
+    fn orientation_tiff(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        // orientation, SHORT, one value, padded to four bytes
+        tiff.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff
+    }
+
+    /// a landscape JPEG whose EXIF data says it was taken with the camera turned by 90°
+    fn sideways_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&RgbImage::new(width, height)).unwrap();
+
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend_from_slice(&orientation_tiff(6));
+
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(&segment);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn sideways_jpegs_get_turned_upright() {
+        let bytes = sideways_jpeg(64, 32);
+        assert_eq!(read_orientation(&bytes), Some(6));
+        let image = apply_orientation(image::load_from_memory(&bytes).unwrap(), 6);
+        assert_eq!((image.width(), image.height()), (32, 64));
+    }
+
+    #[test]
+    fn images_without_exif_have_no_orientation() {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg).encode_image(&RgbImage::new(8, 8)).unwrap();
+        assert_eq!(read_orientation(&jpeg), None);
+        assert_eq!(read_orientation(b"\x89PNG\r\n\x1a\n"), None);
+    }
+
+    #[test]
+    fn every_orientation_moves_the_top_left_corner_into_place() {
+        // where the stored top left pixel of a 3x2 image is shown, and the shown size
+        let cases = [
+            (1, (0, 0), (3, 2)),
+            (2, (2, 0), (3, 2)),
+            (3, (2, 1), (3, 2)),
+            (4, (0, 1), (3, 2)),
+            (5, (0, 0), (2, 3)),
+            (6, (1, 0), (2, 3)),
+            (7, (1, 2), (2, 3)),
+            (8, (0, 2), (2, 3)),
+        ];
+        let red = image::Rgb([255, 0, 0]);
+        let mut stored = RgbImage::new(3, 2);
+        stored.put_pixel(0, 0, red);
+        for (orientation, (x, y), dimensions) in cases {
+            let shown = apply_orientation(DynamicImage::ImageRgb8(stored.clone()), orientation).to_rgb8();
+            assert_eq!(shown.dimensions(), dimensions, "{orientation}");
+            assert_eq!(*shown.get_pixel(x, y), red, "{orientation}");
+        }
+    }
+
+    #[test]
+    fn tiffs_carry_their_own_orientation() {
+        for orientation in 1..=8 {
+            assert_eq!(read_orientation(&orientation_tiff(orientation)), Some(orientation));
+        }
+        // values outside of 1 - 8 are broken tags
+        assert_eq!(read_orientation(&orientation_tiff(9)), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    future::Future,
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+
+use crate::stats::ConversionCounter;
+
+
+/// what the probes of container platforms get to see
+pub struct Health {
+    ready: AtomicBool,
+    conversions: Arc<ConversionCounter>,
+}
+
+impl Health {
+    pub fn new(conversions: Arc<ConversionCounter>) -> Self {
+        Health { ready: AtomicBool::new(false), conversions }
+    }
+
+    /// called once the client is connected to discord
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn router(health: Arc<Health>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .with_state(health)
+}
+
+/// serves the probes until `shutdown` completes
+pub async fn serve(health: Arc<Health>, port: u16, shutdown: impl Future<Output = ()> + Send + 'static) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    axum::serve(listener, router(health)).with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+async fn healthz(State(health): State<Arc<Health>>) -> StatusCode {
+    if health.ready.load(Ordering::SeqCst) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// the conversion counters in the Prometheus text format
+async fn metrics(State(health): State<Arc<Health>>) -> String {
+    let (_, per_palette) = health.conversions.totals();
+    let mut body = String::from(
+        "# HELP image2nord_conversions_total Successful conversions per palette\n\
+         # TYPE image2nord_conversions_total counter\n"
+    );
+    for (palette, count) in per_palette {
+        body.push_str(&format!("image2nord_conversions_total{{palette=\"{}\"}} {count}\n", palette));
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start(health: Arc<Health>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(health)).await });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn healthy_once_ready() {
+        let health = Arc::new(Health::new(Arc::new(ConversionCounter::new())));
+        let url = start(health.clone()).await;
+        let status = reqwest::get(format!("{url}/healthz")).await.unwrap().status();
+        assert_eq!(status.as_u16(), 503);
+
+        health.set_ready();
+        let status = reqwest::get(format!("{url}/healthz")).await.unwrap().status();
+        assert_eq!(status.as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn metrics_count_conversions_per_palette() {
+        let conversions = Arc::new(ConversionCounter::new());
+        conversions.record("nord");
+        conversions.record("nord");
+        conversions.record("gruvbox");
+        let url = start(Arc::new(Health::new(conversions))).await;
+        let body = reqwest::get(format!("{url}/metrics")).await.unwrap().text().await.unwrap();
+        assert!(body.contains("image2nord_conversions_total{palette=\"nord\"} 2\n"), "{body}");
+        assert!(body.contains("image2nord_conversions_total{palette=\"gruvbox\"} 1\n"), "{body}");
+    }
+}
@@ -0,0 +1,21 @@
+//! Converts images into dark color palettes like Nord.
+//!
+//! The Discord bot lives in `main.rs`, everything here works without Discord:
+//!
+//! ```
+//! use image::{DynamicImage, RgbaImage, Rgba};
+//! use image2nord::colors::{self, Models, NordOptions, Palette};
+//!
+//! let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([250, 250, 245, 255])));
+//! let information = colors::calculate_average_brightness(&image.to_rgba8());
+//! let mut options = NordOptions::new();
+//! options.nord = true;
+//! options.palette = Palette::Dracula;
+//! // the background removal models need onnx files, the algorithm works everywhere
+//! options.model = Models::Algorithm;
+//! let converted = colors::apply_nord(image, options, &information);
+//! assert_eq!(converted.width(), 4);
+//! ```
+pub mod colors;
+pub mod config;
+pub mod encode;
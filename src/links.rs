@@ -0,0 +1,142 @@
+use anyhow::{bail, Result};
+use serenity::all::Message;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GifHost {
+    Tenor,
+    Giphy,
+}
+
+#[derive(Clone, Debug)]
+pub struct GifLink {
+    pub host: GifHost,
+    pub url: String,
+}
+
+/// finds all Tenor/Giphy links in the text of a message
+pub fn find_gif_links(content: &str) -> Vec<GifLink> {
+    content
+        .split_whitespace()
+        // discord allows <url> to suppress the embed
+        .map(|word| word.trim_start_matches('<').trim_end_matches('>'))
+        .filter_map(|word| {
+            let host = gif_host(word)?;
+            Some(GifLink { host, url: word.to_string() })
+        })
+        .collect()
+}
+
+/// finds links straight to image files which are not from Tenor/Giphy
+pub fn find_image_urls(content: &str) -> Vec<String> {
+    content
+        .split_whitespace()
+        .map(|word| word.trim_start_matches('<').trim_end_matches('>'))
+        .filter(|word| word.starts_with("https://") || word.starts_with("http://"))
+        .filter(|word| gif_host(word).is_none() && is_direct_media_url(word) && !is_video_url(word))
+        .map(String::from)
+        .collect()
+}
+
+/// images of embeds which don't belong to a link of the message content, like embeds of other bots
+pub fn embed_image_urls(message: &Message) -> Vec<String> {
+    message.embeds
+        .iter()
+        .filter(|embed| {
+            embed.url.as_deref().map_or(true, |url| !message.content.contains(url))
+        })
+        .filter_map(|embed| {
+            embed.image.as_ref().map(|image| image.url.clone())
+                .or_else(|| embed.thumbnail.as_ref().map(|thumbnail| thumbnail.url.clone()))
+        })
+        .collect()
+}
+
+fn gif_host(url: &str) -> Option<GifHost> {
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    let domain = rest.split('/').next()?;
+    let domain = domain.strip_prefix("www.").unwrap_or(domain);
+    match domain {
+        "tenor.com" => Some(GifHost::Tenor),
+        d if d.ends_with(".tenor.com") => Some(GifHost::Tenor),
+        "giphy.com" | "gph.is" => Some(GifHost::Giphy),
+        d if d.ends_with(".giphy.com") => Some(GifHost::Giphy),
+        _ => None,
+    }
+}
+
+fn path_of(url: &str) -> &str {
+    // strip query and fragment
+    url.split(|c| c == '?' || c == '#').next().unwrap_or(url)
+}
+
+pub fn is_video_url(url: &str) -> bool {
+    let path = path_of(url).to_lowercase();
+    path.ends_with(".mp4") || path.ends_with(".webm")
+}
+
+fn is_direct_media_url(url: &str) -> bool {
+    let path = path_of(url).to_lowercase();
+    is_video_url(url)
+        || [".gif", ".png", ".jpg", ".jpeg", ".webp", ".svg"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// resolves a Tenor/Giphy page link to the URL of the underlying media file
+pub async fn resolve_media_url(link: &GifLink) -> Result<String> {
+    if is_direct_media_url(&link.url) {
+        return Ok(link.url.clone());
+    }
+    match link.host {
+        GifHost::Giphy => resolve_giphy(&link.url),
+        GifHost::Tenor => resolve_tenor(&link.url).await,
+    }
+}
+
+fn resolve_giphy(url: &str) -> Result<String> {
+    // giphy.com/gifs/some-slug-<id>, giphy.com/gifs/<id> and giphy.com/embed/<id>
+    let last_segment = path_of(url)
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let id = last_segment.rsplit('-').next().unwrap_or_default();
+    if id.is_empty() || url.contains("gph.is") {
+        bail!("Can't find the GIF id in Giphy link: {}", url);
+    }
+    Ok(format!("https://i.giphy.com/media/{id}/giphy.gif"))
+}
+
+async fn resolve_tenor(url: &str) -> Result<String> {
+    // the page for tenor.com/view/<slug>-<id> carries the gif in its og:image tag
+    let response = reqwest::get(url).await?;
+    if !response.status().is_success() {
+        bail!("Tenor request failed with status code: {}", response.status());
+    }
+    let html = response.text().await?;
+    match find_meta_content(&html, "og:image") {
+        Some(media_url) => Ok(media_url),
+        None => bail!("No media found on Tenor page: {}", url),
+    }
+}
+
+fn find_meta_content(html: &str, property: &str) -> Option<String> {
+    let needle = format!("\"{property}\"");
+    let position = html.find(&needle)?;
+    let tag_start = html[..position].rfind('<')?;
+    let tag_end = position + html[position..].find('>')?;
+    let tag = &html[tag_start..tag_end];
+    let content_start = tag.find("content=\"")? + "content=\"".len();
+    let content_end = content_start + tag[content_start..].find('"')?;
+    Some(tag[content_start..content_end].replace("&amp;", "&"))
+}
+
+/// the image discord generated for the link, used when the link itself can't be resolved
+pub fn embed_media_url(message: &Message, link: &GifLink) -> Option<String> {
+    message.embeds
+        .iter()
+        .filter(|embed| embed.url.as_deref() == Some(link.url.as_str()))
+        .find_map(|embed| {
+            embed.image.as_ref().map(|image| image.url.clone())
+                .or_else(|| embed.thumbnail.as_ref().map(|thumbnail| thumbnail.url.clone()))
+        })
+}
@@ -1,19 +1,28 @@
 #![warn(clippy::str_to_string)]
 mod commands;
-use colors::{ImageInformation, NordOptions};
+use colors::{ConversionStats, Dither, ImageInformation, NordButton, NordOptions, Palette, PaletteColors, RgbColor};
+use encode::{OutputFormat, PngCompression};
+use animation::Animation;
+use rate_limit::RateLimiter;
+use queue::ConversionQueue;
+use originals::{Original, OriginalStore};
+use stats::ConversionCounter;
+use palettes::PaletteRegistry;
+use messages::{fill, Messages};
 use config::Config;
+use image2nord::{colors, config, encode};
 use poise::serenity_prelude as serenity;
 use dotenv::dotenv;
 use ::serenity::all::{
-    Attachment, ButtonStyle, ComponentInteraction, CreateAttachment, 
+    Attachment, AttachmentId, ButtonStyle, ComponentInteraction, CreateActionRow, CreateAttachment, 
     CreateButton, CreateInteractionResponse, CreateInteractionResponseFollowup, 
-    CreateInteractionResponseMessage, CreateMessage, EditAttachments, 
-    EditInteractionResponse, Interaction, Message, ReactionType
+    CreateInteractionResponseMessage, CreateMessage, EditAttachments, EditMessage, 
+    EditInteractionResponse, GuildId, Interaction, Message, MessageId, Reaction, ReactionType, UserId
 };
 use std::{
-    env, io::Cursor, sync::{Arc, Mutex}, time::Duration
+    collections::{HashMap, HashSet, VecDeque}, env, io::Cursor, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, RwLock}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context as _, Result};
 use reqwest;
 use image::DynamicImage;
 
@@ -21,14 +30,26 @@ use image::DynamicImage;
 type AsyncError = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, AsyncError>;
 type SContext = serenity::Context;
-use log::{info, warn};
+use log::{debug, error, info, warn};
 use ttl_cache::TtlCache;
 
-mod config;
-mod colors;
 mod tickbox;
 mod visual_scale;
 mod brightnes_image;
+mod links;
+mod animation;
+mod rate_limit;
+mod swatches;
+mod exif;
+mod cli;
+mod comparison;
+mod queue;
+mod originals;
+mod stats;
+mod messages;
+mod svg;
+mod health;
+mod palettes;
 
 // Custom user data passed to all command functions
 
@@ -46,26 +67,197 @@ impl ImageCache {
     }
 
     async fn get(&self, url: &str) -> Option<(DynamicImage, ImageInformation)> {
-        println!("Checking cache for image");
         let cache = self.cache.lock().expect("cant access cache");
-        println!("Cache: {}", cache.clone().iter().count());
+        debug!("Checking image cache url={url}");
         cache.get(&url.to_string()).cloned()
     }
 
     async fn insert(&self, url: String, information: (DynamicImage, ImageInformation)) -> Option<()> {
-        println!("Inserting image into cache");
+        debug!("Caching image url={url}");
         let mut cache = self.cache.lock().unwrap();
-        println!("Cache insert before: {}", cache.clone().iter().count());
         cache.insert(url.clone(), information, Duration::from_secs(3600));
-        println!("Cache insert after: {}", cache.clone().iter().count());
         
         Some(())
     }
 }
+/// encoded conversions, so clicking the same button again doesn't convert the image twice
+struct ResultCache {
+    cache: Mutex<TtlCache<String, (Vec<u8>, &'static str)>>,
+    /// keys from the least to the most recently used, always locked after cache
+    recency: Mutex<VecDeque<String>>,
+    ttl: Duration,
+    max_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResultCache {
+    fn new(capacity: usize, ttl: Duration, max_bytes: usize) -> Self {
+        ResultCache {
+            cache: Mutex::new(TtlCache::new(capacity)),
+            recency: Mutex::new(VecDeque::new()),
+            ttl,
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// how often a conversion was found in the cache and how often it wasn't
+    fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    /// the options decide the result, so all of them are part of the key and not only the palette.
+    /// The id and not the url identifies the attachment, its signature changes whenever the url is refreshed
+    fn key(attachment: &Attachment, options: &NordOptions) -> String {
+        format!("{}|{}", attachment.id, options.make_nord_custom_id(&0, false, None))
+    }
+
+    fn get(&self, key: &str) -> Option<(Vec<u8>, &'static str)> {
+        let cache = self.cache.lock().unwrap();
+        let result = cache.get(key).cloned();
+        if result.is_some() {
+            let mut recency = self.recency.lock().unwrap();
+            recency.retain(|used| used != key);
+            recency.push_back(key.to_string());
+        }
+        let counter = if result.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    /// drops the least recently used results until the new one fits into max_bytes
+    fn insert(&self, key: String, result: (Vec<u8>, &'static str)) {
+        if result.0.len() > self.max_bytes {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        let mut recency = self.recency.lock().unwrap();
+        // expired results are gone from the cache already
+        recency.retain(|used| used != &key && cache.contains_key(used));
+        let mut used: usize = cache.iter().map(|(_, (buffer, _))| buffer.len()).sum();
+        // the cache itself would drop the oldest insert when it's full, not the least recently used
+        while used + result.0.len() > self.max_bytes || recency.len() >= cache.capacity() {
+            let Some(oldest) = recency.pop_front() else {
+                break;
+            };
+            if let Some((buffer, _)) = cache.remove(&oldest) {
+                used -= buffer.len();
+            }
+        }
+        cache.insert(key.clone(), result, self.ttl);
+        recency.push_back(key);
+    }
+}
+
 pub struct Data {
     image_cache: Arc<ImageCache>,
-    config: Config,
-    
+    result_cache: ResultCache,
+    /// swapped as a whole by /reload, so conversions never see half of an old config
+    config: RwLock<Arc<Config>>,
+    guild_thresholds: Mutex<HashMap<GuildId, f32>>,
+    guild_compression: Mutex<HashMap<GuildId, PngCompression>>,
+    /// palette the prompts of a server offer, picked with /set_palette
+    guild_palettes: Mutex<HashMap<GuildId, Palette>>,
+    /// the strength every user picked last, prompts they answer start with it
+    user_strengths: Mutex<HashMap<UserId, f32>>,
+    /// servers which turned the automatic darken prompt off with /set_auto
+    auto_prompt_disabled: Mutex<HashSet<GuildId>>,
+    custom_palettes: Mutex<HashMap<GuildId, Vec<[u8; 3]>>>,
+    /// palettes of palettes.toml, swapped by /reload
+    palettes: RwLock<Arc<PaletteRegistry>>,
+    rate_limiter: RateLimiter,
+    queue: ConversionQueue,
+    /// messages already converted through a 🌙 reaction
+    moon_conversions: Mutex<HashSet<MessageId>>,
+    /// attachments already scanned, so edits don't prompt for them again
+    seen_attachments: Mutex<TtlCache<AttachmentId, ()>>,
+    originals: OriginalStore,
+    /// creation time of prompts nobody clicked yet, by prompt message
+    prompts: Arc<Mutex<HashMap<MessageId, Instant>>>,
+    conversions: Arc<ConversionCounter>,
+    /// texts by locale, "en" is always there
+    locales: RwLock<Arc<HashMap<String, Messages>>>,
+    guild_locales: Mutex<HashMap<GuildId, String>>,
+}
+
+impl Data {
+    /// brightness above which images get darkened, the default of config.toml outside guilds like in DMs
+    fn brightness_threshold(&self, guild_id: Option<GuildId>) -> f32 {
+        guild_id
+            .and_then(|id| self.guild_thresholds.lock().unwrap().get(&id).copied())
+            .unwrap_or(self.config().threshold.brightness)
+    }
+
+    /// whether bright images get the darken prompt without being asked, always in DMs
+    fn auto_prompt(&self, guild_id: Option<GuildId>) -> bool {
+        !guild_id.is_some_and(|id| self.auto_prompt_disabled.lock().unwrap().contains(&id))
+    }
+
+    fn prompt_palette(&self, guild_id: Option<GuildId>) -> Palette {
+        guild_id
+            .and_then(|id| self.guild_palettes.lock().unwrap().get(&id).copied())
+            .unwrap_or(Palette::Nord)
+    }
+
+    fn png_compression(&self, guild_id: Option<GuildId>) -> PngCompression {
+        guild_id
+            .and_then(|id| self.guild_compression.lock().unwrap().get(&id).copied())
+            .unwrap_or(self.config().output.png_compression)
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    fn locales(&self) -> Arc<HashMap<String, Messages>> {
+        self.locales.read().unwrap().clone()
+    }
+
+    fn palettes(&self) -> Arc<PaletteRegistry> {
+        self.palettes.read().unwrap().clone()
+    }
+
+    /// conversions already running keep the Arc of the old config until they are done
+    fn reload(&self, config: Config, locales: HashMap<String, Messages>, palettes: PaletteRegistry) {
+        *self.config.write().unwrap() = Arc::new(config);
+        *self.locales.write().unwrap() = Arc::new(locales);
+        *self.palettes.write().unwrap() = Arc::new(palettes);
+    }
+
+    /// texts in the locale of the guild, english outside guilds
+    fn messages(&self, guild_id: Option<GuildId>) -> Messages {
+        let locale = guild_id.and_then(|id| self.guild_locales.lock().unwrap().get(&id).cloned());
+        let locales = self.locales();
+        locale
+            .and_then(|locale| locales.get(&locale))
+            .unwrap_or_else(|| &locales[messages::DEFAULT_LOCALE])
+            .clone()
+    }
+
+    /// colors of the palette, the custom palette of the guild if one was set, Nord for DMs
+    fn palette_colors(&self, palette: Palette, guild_id: Option<GuildId>) -> PaletteColors {
+        if palette == Palette::Custom {
+            let custom = guild_id.and_then(|id| self.custom_palettes.lock().unwrap().get(&id).cloned());
+            if let Some(colors) = custom {
+                return PaletteColors::from_rgb(&colors);
+            }
+        }
+        PaletteColors::from_palette(palette)
+    }
+
+    /// every color of the palette, like palette_colors the custom one of the guild
+    fn palette_swatches(&self, palette: Palette, guild_id: Option<GuildId>) -> Vec<RgbColor> {
+        if palette == Palette::Custom {
+            let custom = guild_id.and_then(|id| self.custom_palettes.lock().unwrap().get(&id).cloned());
+            if let Some(colors) = custom {
+                return colors.iter().map(|[r, g, b]| RgbColor::new(*r, *g, *b)).collect();
+            }
+            return Palette::Nord.colors();
+        }
+        palette.colors()
+    }
 }
 
 async fn on_error(error: poise::FrameworkError<'_, Data, AsyncError>) {
@@ -75,11 +267,11 @@ async fn on_error(error: poise::FrameworkError<'_, Data, AsyncError>) {
     match error {
         poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
-            println!("Error in command `{}`: {:?}", ctx.command().name, error,);
+            error!("Command failed command={} error={:?}", ctx.command().name, error);
         }
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
-                println!("Error while handling error: {}", e)
+                error!("Failed to handle error: {}", e)
             }
         }
     }
@@ -88,73 +280,209 @@ async fn on_error(error: poise::FrameworkError<'_, Data, AsyncError>) {
 async fn interaction_create(ctx: SContext, interaction: Interaction, data: &Data) -> Option<()> {
     if let Interaction::Component(interaction) = interaction {
         let content = &interaction.data.custom_id;
-        if content.starts_with("darken-") {
-            handle_interaction_darkening(&ctx, &interaction, data).await.unwrap();
-        }
-        if content.starts_with("delete-") {
-            let message_id = content.split("-").last().unwrap().parse::<u64>().unwrap();
-            handle_dispose(&ctx, &interaction, message_id).await.unwrap();
-        }
-        if content.starts_with("clear-") {
-            initial_clear_components(&ctx, &interaction).await.unwrap();
-        }
-        if content.starts_with("stop-") {
-            let response = CreateInteractionResponse::UpdateMessage(CreateInteractionResponseMessage::default());
-            interaction.create_response(&ctx, response).await.unwrap();
-            interaction.delete_response(&ctx).await.unwrap();
+        let result = if content.starts_with("darken-") {
+            handle_interaction_darkening(&ctx, &interaction, data).await
+        } else if content.starts_with("delete-") {
+            match message_id_of(content) {
+                Ok(message_id) => handle_dispose(&ctx, &interaction, message_id, data).await,
+                Err(e) => Err(e),
+            }
+        } else if content.starts_with("restore-") {
+            match message_id_of(content) {
+                Ok(message_id) => handle_restore(&ctx, &interaction, message_id, data).await,
+                Err(e) => Err(e),
+            }
+        } else if content.starts_with("clear-") {
+            initial_clear_components(&ctx, &interaction).await
+        } else if content.starts_with("usage-") {
+            handle_usage(&ctx, &interaction, data).await
+        } else if content.starts_with("diff-") {
+            handle_diff(&ctx, &interaction, data).await
+        } else if content.starts_with("cancel-") {
+            handle_cancel(&ctx, &interaction, data).await
+        } else if content.starts_with("stop-") {
+            handle_stop(&ctx, &interaction).await
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            report_error(&ctx, &interaction, e).await;
         }
     }
     Some(())
 }
 
+/// logs the error and tells the user what went wrong
+async fn report_error(ctx: &SContext, interaction: &ComponentInteraction, error: anyhow::Error) {
+    error!("Interaction `{}` failed: {:?}", interaction.data.custom_id, error);
+    let content = error.to_string();
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new().content(&content).ephemeral(true)
+    );
+    if interaction.create_response(&ctx, response).await.is_err() {
+        // the interaction was already acknowledged
+        let followup = CreateInteractionResponseFollowup::new().content(content).ephemeral(true);
+        if let Err(e) = interaction.create_followup(&ctx, followup).await {
+            error!("Failed to report error to user: {}", e);
+        }
+    }
+}
+
+/// the message id is always the last part of a custom id
+fn message_id_of(custom_id: &str) -> Result<u64> {
+    custom_id
+        .rsplit("-")
+        .next()
+        .unwrap_or_default()
+        .parse::<u64>()
+        .with_context(|| format!("Invalid message id in button `{custom_id}`"))
+}
+
 async fn fetch_or_raise_message(
     ctx: &SContext, 
     interaction: &ComponentInteraction, 
     message_id: u64
-) -> Message {
-    let message = interaction.channel_id.message(&ctx, message_id).await;
-    if message.is_err() {
-        // fetch image message -> error
-        let response = CreateInteractionResponse::Message(CreateInteractionResponseMessage::new()
-            .content("Seems like the bright picture has vanished. I can't darken what I can't see.")
-        );
-        interaction.create_response(&ctx, response).await.unwrap();
+) -> Result<Message> {
+    interaction.channel_id.message(&ctx, message_id).await
+        .context("Seems like the bright picture has vanished. I can't darken what I can't see.")
+}
+
+async fn handle_stop(ctx: &SContext, interaction: &ComponentInteraction) -> Result<()> {
+    let response = CreateInteractionResponse::UpdateMessage(CreateInteractionResponseMessage::default());
+    interaction.create_response(&ctx, response).await?;
+    interaction.delete_response(&ctx).await?;
+    Ok(())
+}
+
+/// sends a chart of how often each palette color appears in the converted image
+async fn handle_usage(ctx: &SContext, interaction: &ComponentInteraction, data: &Data) -> Result<()> {
+    let palette = interaction.data.custom_id
+        .split('-')
+        .nth(1)
+        .and_then(Palette::from_str)
+        .unwrap_or(Palette::Nord);
+    let converted = interaction.message.attachments
+        .iter()
+        .find(|attachment| !attachment.filename.contains("-comparison."))
+        .context("There is no converted image yet. Press Start first.")?;
+    let response = CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new().ephemeral(true));
+    interaction.create_response(&ctx, response).await?;
+
+    let image = download_from_url(&converted.url, data.config().limits.max_megapixels).await?;
+    let stats = ConversionStats::from_image(&image.to_rgba8());
+    let chart = swatches::render_usage_chart(&stats, &data.palette_swatches(palette, interaction.guild_id));
+    let buffer = encode::encode_image(&chart, OutputFormat::Png)?;
+    let followup = CreateInteractionResponseFollowup::new()
+        .content(format!("{} colors in the {} conversion", stats.colors_used, palette.name()))
+        .add_file(CreateAttachment::bytes(buffer, "usage.png"))
+        .ephemeral(true);
+    interaction.create_followup(&ctx, followup).await?;
+    Ok(())
+}
+
+/// sends a heatmap of where the conversion changed the original and by how much
+async fn handle_diff(ctx: &SContext, interaction: &ComponentInteraction, data: &Data) -> Result<()> {
+    let message_id = message_id_of(&interaction.data.custom_id)?;
+    let converted = interaction.message.attachments
+        .iter()
+        .find(|attachment| !attachment.filename.contains("-comparison."))
+        .context("There is no converted image yet. Press Start first.")?;
+    let response = CreateInteractionResponse::Defer(CreateInteractionResponseMessage::new().ephemeral(true));
+    interaction.create_response(&ctx, response).await?;
+
+    let message = fetch_or_raise_message(ctx, interaction, message_id).await?;
+    let original = message.attachments.first()
+        .context("Seems like the bright picture has vanished. I can't darken what I can't see.")?;
+    let (original, _info) = fetch_image_and_info(original, data).await?;
+    let converted = download_from_url(&converted.url, data.config().limits.max_megapixels).await?;
+    let diff = comparison::render_diff(&original, &converted);
+    let buffer = encode::encode_image(&diff, OutputFormat::Png)?;
+    let followup = CreateInteractionResponseFollowup::new()
+        .content("The brighter, the more the color changed")
+        .add_file(CreateAttachment::bytes(buffer, "diff.png"))
+        .ephemeral(true);
+    interaction.create_followup(&ctx, followup).await?;
+    Ok(())
+}
+
+/// takes a job out of the line, the waiting darkening handler then shows that it was cancelled
+async fn handle_cancel(ctx: &SContext, interaction: &ComponentInteraction, data: &Data) -> Result<()> {
+    let token = interaction.data.custom_id
+        .strip_prefix("cancel-")
+        .and_then(|token| token.parse::<u64>().ok())
+        .context("Invalid cancel button")?;
+    if !data.queue.cancel(token) {
+        bail!(data.messages(interaction.guild_id).already_started);
     }
-    message.unwrap()
+    interaction.create_response(&ctx, CreateInteractionResponse::Acknowledge).await?;
+    Ok(())
 }
 
 async fn handle_interaction_darkening(ctx: &SContext, interaction: &ComponentInteraction, data: &Data) -> Result<()> {
     let content = &interaction.data.custom_id;
-    let mut options = NordOptions::from_custom_id(&content);
-    let message_id = content.split("-").last().unwrap().parse::<u64>()?;
-    let _update = content.split("-").nth(1).unwrap().parse::<bool>().unwrap_or(true);
+    let texts = data.messages(interaction.guild_id);
+    let NordButton { mut options, update, message_id, .. } = NordButton::decode(content)
+        .context(texts.outdated_button.clone())?;
+
+    if !update {
+        // the first click answers the prompt, from then on the buttons belong to the conversion
+        let created = data.prompts.lock().unwrap().remove(&interaction.message.id);
+        let timeout = Duration::from_secs(data.config().prompt.expire_secs);
+        // untracked prompts are from before a restart
+        if created.map_or(true, |created| prompt_expired(created, Instant::now(), timeout)) {
+            bail!(texts.prompt_expired);
+        }
+        if let Some(strength) = data.user_strengths.lock().unwrap().get(&interaction.user.id) {
+            options.strength = *strength;
+        }
+    } else {
+        data.user_strengths.lock().unwrap().insert(interaction.user.id, options.strength);
+    }
 
     let mut message: Option<Message> = None;
     if options.auto_adjust {
-        message = Some(fetch_or_raise_message(&ctx, &interaction, message_id).await);
-        let ref unwrapped = message.as_ref().unwrap();
-        let (_image, information) = fetch_image(unwrapped.attachments.first().unwrap(), data).await;
+        let fetched = fetch_or_raise_message(&ctx, &interaction, message_id).await?;
+        let attachment = fetched.attachments.first()
+            .context(texts.image_vanished.clone())?;
+        let (_image, information) = fetch_image_and_info(attachment, data).await
+            .context("Sorry, I couldn't grab that image.")?;
+        message = Some(fetched);
         let new_options = NordOptions::from_image_information(&information);
-        options = NordOptions {start: options.start, ..new_options};
+        options = NordOptions {start: options.start, ..new_options}.with_settings_of(&options);
+    }
+
+    // the other prompt buttons ask for the palette mapping, which the picture presets leave off
+    if !update && (options.dither != Dither::None || options.preserve_luminance || options.gradient_map || options.bright_only) {
+        options.nord = true;
     }
 
     let new_components = options.build_componets(message_id, true);
     
-    println!("options: {:?}", options);
+    debug!("Darkening options user={} options={:?}", interaction.user.id, options);
+
+    if options.start && data.rate_limiter.is_rate_limited(interaction.user.id, Instant::now()) {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(&texts.rate_limited)
+                .ephemeral(true)
+        );
+        interaction.create_response(&ctx, response).await?;
+        return Ok(())
+    }
 
     if options.start {
         // start button pressed
         if message.is_none() {
-            message = Some(fetch_or_raise_message(&ctx, &interaction, message_id).await);
+            message = Some(fetch_or_raise_message(&ctx, &interaction, message_id).await?);
         }
         let response = CreateInteractionResponse::Acknowledge;
         interaction.create_response(&ctx, response).await?;
         // edit response with new components
         let response = EditInteractionResponse::new()
             .attachments(EditAttachments::keep_all(&interaction.message))
-            .content("⌛ I'm working on it. Please wait a moment.")
+            .content(&texts.working)
             .components(new_components.clone());
-        interaction.edit_response(&ctx, response).await.unwrap();
+        interaction.edit_response(&ctx, response).await?;
     } else {
         // first ack, that existing image is being kept
         let response = CreateInteractionResponse::Acknowledge;
@@ -162,14 +490,14 @@ async fn handle_interaction_darkening(ctx: &SContext, interaction: &ComponentInt
         // edit response with new components
         let response = EditInteractionResponse::new()
             .attachments(EditAttachments::keep_all(&interaction.message))
-            .content("⌛ I change the options. Please wait a moment.")
+            .content(&texts.changing_options)
             .components(new_components.clone());
-        interaction.edit_response(&ctx, response).await.unwrap();
+        interaction.edit_response(&ctx, response).await?;
     }
     
     if !options.start {
         let response = EditInteractionResponse::new()
-            .content("Edited your options.")
+            .content(&texts.options_changed)
             .components(new_components.clone())
         ;
         interaction.edit_response(&ctx, response).await?;
@@ -178,47 +506,224 @@ async fn handle_interaction_darkening(ctx: &SContext, interaction: &ComponentInt
     // ensure existence of message
     if message.is_none() {
         interaction.edit_response(&ctx, EditInteractionResponse::new()
-            .content("Seems like the bright picture has vanished. I can't darken what I can't see.")
+            .content(&texts.image_vanished)
         ).await?;
         return Ok(())
     }
     let message = message.unwrap();
+    let position = data.queue.position();
+    let token = data.queue.enqueue();
+    if position > 0 {
+        let cancel = CreateButton::new(format!("cancel-{token}"))
+            .style(ButtonStyle::Danger)
+            .label(&texts.cancel_button);
+        interaction.edit_response(&ctx, EditInteractionResponse::new()
+            .content(fill(&texts.in_line, "position", position))
+            .components(vec![CreateActionRow::Buttons(vec![cancel])])
+        ).await?;
+    }
+    // released when this handler returns, also on errors
+    let Some(_permit) = data.queue.acquire_pending(token).await else {
+        interaction.edit_response(&ctx, EditInteractionResponse::new()
+            .content(&texts.cancelled)
+            .components(new_components)
+        ).await?;
+        return Ok(())
+    };
+    if position > 0 {
+        interaction.edit_response(&ctx, EditInteractionResponse::new()
+            .content(&texts.working)
+            .components(new_components.clone())
+        ).await?;
+    }
     // process image
-    let buffer = match process_attachments(&message, &data, &options).await {
-        Ok(buffer) => buffer,
+    let start = Instant::now();
+    // the conversion blocks, so another task shows the latest progress every few seconds
+    let (progress_sender, mut progress_receiver) = tokio::sync::watch::channel(None::<Progress>);
+    let progress_updater = {
+        let (ctx, interaction, texts) = (ctx.clone(), interaction.clone(), texts.clone());
+        tokio::spawn(async move {
+            while progress_receiver.changed().await.is_ok() {
+                let description = progress_receiver.borrow_and_update().as_ref().map(|progress| progress.describe(&texts));
+                if let Some(description) = description {
+                    let response = EditInteractionResponse::new().content(description);
+                    if let Err(e) = interaction.edit_response(&ctx, response).await {
+                        warn!("Failed to show progress: {}", e);
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        })
+    };
+    let report_progress = move |progress: Progress| {
+        progress_sender.send_replace(Some(progress));
+    };
+    let processed = process_attachments(&message, interaction.guild_id, &data, &options, &report_progress).await;
+    progress_updater.abort();
+    let processed = match processed {
+        Ok(processed) => processed,
         Err(e) => {
             interaction.edit_response(&ctx, EditInteractionResponse::default().content(e.to_string())).await?;
             return Ok(())
         }
     };
-    let attachment = CreateAttachment::bytes(buffer, "image.webp");
-    let content = EditInteractionResponse::new()
-        .new_attachment(attachment)
-        .content("Here it is! May I delete your shiny one?")
+    // dimmed images keep their own colors
+    let palette_note = if options.dim {
+        String::new()
+    } else {
+        format!("\n{}", fill(&texts.palette_used, "palette", options.palette.name()))
+    };
+    let mut content = EditInteractionResponse::new()
+        .content(format!(
+            "{}{}{}{}{}", 
+            texts.done, palette_note, processed.skipped_note(&texts),
+            processed.downscaled_note(data.config().limits.max_dimension), processed.stats_note(start.elapsed())
+        ))
         .components(new_components.clone())
     ;
-    // stone emoji: 
-    println!("sending message");
+    for attachment in processed.files {
+        content = content.new_attachment(attachment);
+    }
+    log_conversion(interaction.user.id, &processed.stats, options.palette, start.elapsed());
     interaction.edit_response(&ctx, content).await?;
     Ok(())
 }
 
-pub async fn process_attachments(message: &Message, data: &Data, options: &NordOptions) -> Result<Vec<u8>, AsyncError>{
+/// one info record per answered click, the size is the one of the first still image
+fn log_conversion(user: UserId, stats: &[ConversionStats], palette: Palette, elapsed: Duration) {
+    let (width, height) = stats.first().map_or((0, 0), |stats| (stats.width, stats.height));
+    info!(
+        "converted user={} width={} height={} palette={} elapsed_ms={}",
+        user, width, height, palette.as_str(), elapsed.as_millis()
+    );
+}
+
+pub struct ProcessedAttachments {
+    pub files: Vec<CreateAttachment>,
+    /// how many attachments were converted, comparisons come as extra files
+    pub converted: usize,
+    /// filename and reason of every attachment which was not converted
+    pub skipped: Vec<(String, String)>,
+    /// stats of the still images which were converted, cached results have none
+    pub stats: Vec<ConversionStats>,
+    /// filenames of the images which were larger than limits.max_dimension
+    pub downscaled: Vec<String>,
+    /// filename and what the animation limits changed about it
+    pub reduced: Vec<(String, String)>,
+}
+
+impl ProcessedAttachments {
+    pub fn skipped_note(&self, texts: &Messages) -> String {
+        if self.skipped.is_empty() {
+            return String::new();
+        }
+        let skipped = self.skipped
+            .iter()
+            .map(|(filename, reason)| format!("- `{filename}`: {reason}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("\n{}\n{skipped}", texts.skipped)
+    }
+
+    pub fn downscaled_note(&self, max_dimension: u32) -> String {
+        let mut note = String::new();
+        if !self.downscaled.is_empty() {
+            let files = self.downscaled.iter().map(|filename| format!("`{filename}`")).collect::<Vec<_>>().join(", ");
+            note.push_str(&format!("\nI scaled {files} down to at most {max_dimension}px per side first."));
+        }
+        for (filename, changes) in &self.reduced {
+            note.push_str(&format!("\n`{filename}` was too long or big for me, I {changes}."));
+        }
+        note
+    }
+
+    /// footer like "Converted 1920×1080 in 2.3s, 42 palette colors used, metadata removed"
+    pub fn stats_note(&self, elapsed: Duration) -> String {
+        if self.stats.is_empty() {
+            return String::new();
+        }
+        let stats = self.stats
+            .iter()
+            .map(|stats| format!("{}×{}", stats.width, stats.height))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let colors_used: usize = self.stats.iter().map(|stats| stats.colors_used).sum();
+        format!("\n-# Converted {stats} in {:.1}s, {colors_used} palette colors used, metadata removed", elapsed.as_secs_f32())
+    }
+}
+
+/// converts every image attachment of the message, 
+/// guild_id is passed separately since fetched messages don't carry it and it is None in DMs
+pub async fn process_attachments(
+    message: &Message, 
+    guild_id: Option<GuildId>, 
+    data: &Data, 
+    options: &NordOptions,
+    progress: ProgressCallback<'_>
+) -> Result<ProcessedAttachments, AsyncError>{
+    let mut processed = ProcessedAttachments { files: Vec::new(), converted: 0, skipped: Vec::new(), stats: Vec::new(), downscaled: Vec::new(), reduced: Vec::new() };
+    let max_dimension = data.config().limits.max_dimension;
+    let max_upload_bytes = (data.config().limits.max_upload_mib * 1024.0 * 1024.0) as usize;
     for attachment in &message.attachments {
-        println!("Processing attachment");
-        let image = process_image(&attachment, data, options.clone()).await.unwrap();
-        println!("writing image to buffer");
-        let mut buffer = Vec::new();
-            // Create a PNG encoder with a specific compression level
-        {
-            let mut cursor = Cursor::new(&mut buffer);
-            //let encoder = PngEncoder::new_with_quality(&mut cursor, CompressionType::Fast, FilterType::Adaptive);
-            image.write_to( &mut cursor, image::ImageFormat::WebP).expect("Failed to write image to buffer");
-            //encoder.write_image(&image.as_bytes(), image.width(), image.height(), image::ExtendedColorType::Rgba8).unwrap();
+        if let Err(e) = image_check(attachment).await {
+            processed.skipped.push((attachment.filename.clone(), e.to_string()));
+            continue;
         }
-        return Ok(buffer);
+        // the custom palette of a guild can change at any time, comparisons are a second file
+        let cache_key = (options.palette != Palette::Custom && !options.compare)
+            .then(|| ResultCache::key(attachment, options));
+        let cached = cache_key.as_deref().and_then(|key| data.result_cache.get(key));
+        let (buffer, extension) = if let Some(cached) = cached {
+            debug!("Using cached result file={}", attachment.filename);
+            cached
+        } else {
+            debug!("Processing attachment file={}", attachment.filename);
+            let image = match process_image(&attachment, guild_id, data, options.clone(), ConversionExtras::default(), progress).await {
+                Ok(image) => image,
+                Err(e) => {
+                    warn!("Failed to process {}: {:?}", attachment.filename, e);
+                    processed.skipped.push((attachment.filename.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            let encode_options = encode::EncodeOptions {
+                background: data.palette_colors(options.palette, guild_id).background(),
+                png_compression: data.png_compression(guild_id),
+            };
+            if let ProcessedImage::Animation(_, Some(note)) = &image {
+                processed.reduced.push((attachment.filename.clone(), note.clone()));
+            }
+            let encoded = encode_processed(image, options.format, encode_options, max_upload_bytes).await;
+            let (stats, comparison, result) = match encoded {
+                Ok(encoded) => encoded,
+                Err(e) => {
+                    warn!("Failed to encode {}: {:?}", attachment.filename, e);
+                    processed.skipped.push((attachment.filename.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            processed.stats.extend(stats);
+            if let Some((buffer, format)) = comparison {
+                let name = output_filename(&attachment.filename, "-comparison", format.extension());
+                processed.files.push(CreateAttachment::bytes(buffer, name));
+            }
+            if let Some(key) = cache_key {
+                data.result_cache.insert(key, result.clone());
+            }
+            result
+        };
+        processed.files.push(CreateAttachment::bytes(buffer, output_filename(&attachment.filename, "", extension)));
+        processed.converted += 1;
+        // animations keep their size, the still images went through fit_within
+        let longest_side = attachment.width.max(attachment.height).unwrap_or(0);
+        if extension != "gif" && longest_side > max_dimension {
+            processed.downscaled.push(attachment.filename.clone());
+        }
+    }
+    if processed.files.is_empty() {
+        return Err(format!("I found no image to darken.{}", processed.skipped_note(&data.messages(guild_id))).into());
     }
-    panic!("No attachment found in message");
+    Ok(processed)
 }
 async fn initial_clear_components(ctx: &SContext, interaction: &ComponentInteraction) -> Result<()> {
     // fetch message
@@ -232,29 +737,76 @@ async fn initial_clear_components(ctx: &SContext, interaction: &ComponentInterac
     Ok(())
 }
 
-async fn handle_dispose(ctx: &SContext, interaction: &ComponentInteraction, message_id: u64) -> Result<()> {
+async fn handle_dispose(ctx: &SContext, interaction: &ComponentInteraction, message_id: u64, data: &Data) -> Result<()> {
+    let texts = data.messages(interaction.guild_id);
+    if interaction.guild_id.is_none() {
+        bail!(texts.no_delete_in_dm.clone());
+    }
     initial_clear_components(&ctx, &interaction).await?;
     // fetch message
+    let message = interaction.channel_id.message(&ctx, message_id).await?;
+    // keep the original for a moment, in case it was disposed by accident
+    let mut files = Vec::new();
+    for attachment in &message.attachments {
+        match download_bytes(&attachment.url).await {
+            Ok(bytes) => files.push((attachment.filename.clone(), bytes)),
+            Err(e) => warn!("Can't keep {} for restoring: {:?}", attachment.filename, e),
+        }
+    }
+    data.originals.insert(message_id, Original { author: message.author.id, files });
     interaction.channel_id.delete_message(&ctx, message_id).await?;
+    let restore_button = CreateButton::new(format!("restore-{}", message_id))
+        .style(ButtonStyle::Secondary)
+        .label(&texts.restore_button)
+        .emoji("↩️".parse::<ReactionType>().unwrap());
     let response =
         CreateInteractionResponseFollowup::new()
-        .content("I have thrown it deep into the void to never see it again. Enjoy the darkness!")
+        .content(format!(
+            "{} {}", 
+            texts.disposed, fill(&texts.restore_hint, "minutes", originals::RESTORE_WINDOW.as_secs() / 60)
+        ))
+        .components(vec![CreateActionRow::Buttons(vec![restore_button])])
         .ephemeral(true)
     ;
     interaction.create_followup(&ctx, response).await?;
     Ok(())
 }
 
+async fn handle_restore(ctx: &SContext, interaction: &ComponentInteraction, message_id: u64, data: &Data) -> Result<()> {
+    let original = data.originals.take(message_id)
+        .with_context(|| data.messages(interaction.guild_id).restore_expired.clone())?;
+    let response = CreateInteractionResponse::Acknowledge;
+    interaction.create_response(&ctx, response).await?;
+    let mut message = CreateMessage::new()
+        .content(fill(&data.messages(interaction.guild_id).restored, "user", format!("<@{}>", original.author)));
+    for (filename, bytes) in original.files {
+        message = message.add_file(CreateAttachment::bytes(bytes, filename));
+    }
+    interaction.channel_id.send_message(&ctx, message).await?;
+    Ok(())
+}
+
 
 #[tokio::main]
 async fn main() {
-    // env_logger::init();
+    // RUST_LOG=debug shows every event and download
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("convert") {
+        if let Err(e) = cli::convert(&args[2..]) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
     dotenv().ok();
     // FrameworkOptions contains all of poise's configuration option in one struct
     // Every option can be omitted to use its default value
     let image_cache = Arc::new(ImageCache::new());
     let options = poise::FrameworkOptions {
-        commands: vec![commands::edit_message_image(), commands::help()],
+        commands: vec![commands::edit_message_image(), commands::help(), commands::nord(),
+            commands::set_threshold(), commands::threshold(), commands::palette(), commands::set_custom_palette(), commands::reset_custom_palette(), 
+            commands::stats(), commands::set_locale(), commands::reload(), commands::nordlast(), commands::nordbatch(), commands::set_compression(), commands::set_auto(), commands::set_palette(), commands::analyze()],
         prefix_options: poise::PrefixFrameworkOptions {
             prefix: Some("~".into()),
             edit_tracker: Some(Arc::new(poise::EditTracker::for_timespan(
@@ -271,13 +823,13 @@ async fn main() {
         // This code is run before every command
         pre_command: |ctx| {
             Box::pin(async move {
-                println!("Executing command {}...", ctx.command().qualified_name);
+                debug!("Executing command command={}", ctx.command().qualified_name);
             })
         },
         // This code is run after a command if it was successful (returned Ok)
         post_command: |ctx| {
             Box::pin(async move {
-                println!("Executed command {}!", ctx.command().qualified_name);
+                debug!("Executed command command={}", ctx.command().qualified_name);
             })
         },
         // Every command invocation must pass this check to continue execution
@@ -298,33 +850,108 @@ async fn main() {
         ..Default::default()
     };
 
+    // the file on disk wins, so /reload and a restart see the same config
+    let config_path = config::config_path();
+    let config = if config_path.exists() {
+        match config::read_config(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                // falling back to the built in config would hide the typo until someone wonders why a setting does nothing
+                error!("Failed to read {}: {:#}", config_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        info!("No {} found, using the built in config", config_path.display());
+        config::load_config()
+    };
+    let config = config::with_env_overrides(config);
+    let conversions = Arc::new(ConversionCounter::new());
+    let health = Arc::new(health::Health::new(conversions.clone()));
+    let (shutdown_sender, mut shutdown_receiver) = tokio::sync::watch::channel(false);
+    {
+        let (health, port) = (health.clone(), config.health.port);
+        let shutdown = async move {
+            let _ = shutdown_receiver.wait_for(|stop| *stop).await;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(health, port, shutdown).await {
+                error!("Health server failed port={} error={:?}", port, e);
+            }
+        });
+    }
+
+    let ready_health = health.clone();
     let framework = poise::Framework::builder()
         .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
-                println!("Logged in as {}", _ready.user.name);
+                info!("Logged in user={}", _ready.user.name);
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                ready_health.set_ready();
                 Ok(Data {
                     image_cache: image_cache,
-                    config: config::load_config(),
+                    result_cache: ResultCache::new(
+                        config.cache.result_capacity, 
+                        Duration::from_secs(config.cache.result_ttl_secs),
+                        config.cache.result_max_mib * 1024 * 1024
+                    ),
+                    queue: ConversionQueue::new(config.limits.max_concurrent_conversions),
+                    config: RwLock::new(Arc::new(config)),
+                    guild_thresholds: Mutex::new(HashMap::new()),
+                    guild_compression: Mutex::new(HashMap::new()),
+                    guild_palettes: Mutex::new(HashMap::new()),
+                    user_strengths: Mutex::new(HashMap::new()),
+                    auto_prompt_disabled: Mutex::new(HashSet::new()),
+                    custom_palettes: Mutex::new(HashMap::new()),
+                    palettes: RwLock::new(Arc::new(PaletteRegistry::load())),
+                    rate_limiter: RateLimiter::from_env(),
+                    moon_conversions: Mutex::new(HashSet::new()),
+                    seen_attachments: Mutex::new(TtlCache::new(1000)),
+                    originals: OriginalStore::new(20, originals::RESTORE_WINDOW),
+                    prompts: Arc::new(Mutex::new(HashMap::new())),
+                    conversions,
+                    locales: RwLock::new(Arc::new(messages::load_locales(&messages::locales_dir()))),
+                    guild_locales: Mutex::new(HashMap::new()),
                 })
             })
         })
         .options(options)
         .build();
 
-    for (key, value) in env::vars() {
-        println!("{}: {}", key, value);
-    }
     // load DISCORD_TOKEN from .env file
     let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN must be set in .env");
     let intents =
         serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::MESSAGE_CONTENT;
 
-    let client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .await;
+        .await
+        .expect("Failed to create the discord client");
 
-    client.unwrap().start().await.unwrap()
+    // disconnects cleanly and stops the health server on ctrl+c and SIGTERM
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down");
+        let _ = shutdown_sender.send(true);
+        shard_manager.shutdown_all().await;
+    });
+
+    client.start().await.unwrap()
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 
@@ -334,29 +961,49 @@ async fn event_handler(
     _framework: poise::FrameworkContext<'_, Data, AsyncError>,
     data: &Data,
 ) -> Result<(), AsyncError> {
-    println!(
-        "Got an event in event handler: {:?}",
-        event.snake_case_name()
-    );
+    debug!("Received event event={}", event.snake_case_name());
 
     match event {
         serenity::FullEvent::Ready { data_about_bot, .. } => {
-            println!("Logged in as {}", data_about_bot.user.name);
+            info!("Logged in user={}", data_about_bot.user.name);
         }
         serenity::FullEvent::InteractionCreate { interaction, .. } => {
             interaction_create(ctx.clone(), interaction.clone(), data).await;
         }
         serenity::FullEvent::Message { new_message: message } => {
-            for attachment in &message.attachments {
-                if message.author.bot {
-                    continue;
+            scan_attachments(ctx, message, data).await;
+            if message.author.bot {
+                return Ok(());
+            }
+            for link in links::find_gif_links(&message.content) {
+                debug!("Found GIF link url={}", link.url);
+                convert_gif_link(&ctx, &message, &link, data).await?;
+            }
+            let mut image_urls = links::find_image_urls(&message.content);
+            image_urls.extend(links::embed_image_urls(message));
+            for url in image_urls {
+                debug!("Found image link url={}", url);
+                let content = data.messages(message.guild_id).linked_image_done;
+                // links can point anywhere, so failures are expected and only logged
+                if let Err(e) = convert_linked_image(&ctx, &message, &url, &content, data).await {
+                    warn!("Rejected linked image url={} reason={}", url, e);
                 }
-                println!("attachment found");
-                println!(
-                    "media type: {:?}; filename: {}; Size: {} MiB; URL: {}", 
-                    attachment.content_type, attachment.filename, attachment.size as f64 / 1024.0 / 1024.0, attachment.url
-                );
-                ask_user_to_darken_image(&ctx, &message, &attachment, data).await?;
+            }
+        }
+        serenity::FullEvent::MessageUpdate { new, event, .. } => {
+            // only edits which add attachments are interesting
+            if event.attachments.as_ref().map_or(true, |attachments| attachments.is_empty()) {
+                return Ok(());
+            }
+            let message = match new {
+                Some(message) => message.clone(),
+                None => event.channel_id.message(&ctx, event.id).await?,
+            };
+            scan_attachments(ctx, &message, data).await;
+        }
+        serenity::FullEvent::ReactionAdd { add_reaction } => {
+            if let Err(e) = handle_moon_reaction(ctx, add_reaction, data).await {
+                warn!("Failed to darken after reaction: {:?}", e);
             }
         }
         _ => {}
@@ -364,22 +1011,168 @@ async fn event_handler(
     Ok(())
 }
 
+/// discord hides attachments whose filename starts with this until they get clicked
+const SPOILER_PREFIX: &str = "SPOILER_";
+
+fn is_spoiler(filename: &str) -> bool {
+    filename.starts_with(SPOILER_PREFIX)
+}
+
+/// name of a converted file, behind a spoiler again if the original was
+fn output_filename(original: &str, suffix: &str, extension: &str) -> String {
+    let stem = original.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(original);
+    let stem = stem.strip_prefix(SPOILER_PREFIX).unwrap_or(stem);
+    let prefix = if is_spoiler(original) { SPOILER_PREFIX } else { "" };
+    format!("{prefix}{stem}{suffix}.{extension}")
+}
+
+
+/// offers to darken the attachments of the message which weren't seen before
+async fn scan_attachments(ctx: &SContext, message: &Message, data: &Data) {
+    if message.author.bot {
+        return;
+    }
+    let new_attachments: Vec<&Attachment> = {
+        let mut seen = data.seen_attachments.lock().unwrap();
+        message.attachments
+            .iter()
+            .filter(|attachment| {
+                let is_new = !seen.contains_key(&attachment.id);
+                seen.insert(attachment.id, (), Duration::from_secs(3600));
+                is_new
+            })
+            .collect()
+    };
+    for attachment in new_attachments {
+        debug!(
+            "Found attachment type={:?} file={} size_mib={:.2} url={}", 
+            attachment.content_type, attachment.filename, attachment.size as f64 / 1024.0 / 1024.0, attachment.url
+        );
+        // one prompt converts all attachments of the message
+        match ask_user_to_darken_image(&ctx, &message, &attachment, data).await {
+            Ok(()) => break,
+            Err(e) => warn!("Rejected attachment file={} reason={}", attachment.filename, e),
+        }
+    }
+}
+
+/// darkens the images of a message when someone reacts with 🌙
+async fn handle_moon_reaction(ctx: &SContext, reaction: &Reaction, data: &Data) -> Result<()> {
+    if !reaction.emoji.unicode_eq("🌙") {
+        return Ok(());
+    }
+    let Some(user_id) = reaction.user_id else {
+        return Ok(());
+    };
+    if user_id.to_user(&ctx).await?.bot {
+        return Ok(());
+    }
+    let message = reaction.message(&ctx).await?;
+    // a missing content type gets sniffed later, so it might still be an image
+    let has_image = message.attachments.iter().any(|attachment| {
+        attachment.content_type.as_deref().map_or(true, |content_type| content_type.starts_with("image/"))
+    });
+    if message.author.bot || !has_image {
+        return Ok(());
+    }
+    if data.rate_limiter.is_rate_limited(user_id, Instant::now()) {
+        return Ok(());
+    }
+    // removing and adding the reaction again must not convert the message again
+    if !data.moon_conversions.lock().unwrap().insert(message.id) {
+        return Ok(());
+    }
+    let converted = convert_moon_reaction(ctx, &message, reaction.guild_id, data).await;
+    // only successful conversions count, so a failed one can be retried with the reaction
+    if converted.is_err() {
+        data.moon_conversions.lock().unwrap().remove(&message.id);
+    }
+    converted
+}
+
+async fn convert_moon_reaction(ctx: &SContext, message: &Message, guild_id: Option<GuildId>, data: &Data) -> Result<()> {
+    let (_image, info) = fetch_image_and_info(&message.attachments[0], data).await?;
+    let mut options = NordOptions::from_image_information(&info);
+    options.start = true;
+    let _permit = data.queue.acquire().await;
+    let processed = process_attachments(message, guild_id, data, &options, &|_| {}).await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let texts = data.messages(guild_id);
+    let mut response = CreateMessage::new()
+        .content(format!(
+            "{}{}{}",
+            texts.moon_done, processed.skipped_note(&texts), processed.downscaled_note(data.config().limits.max_dimension)
+        ))
+        .reference_message(message);
+    for file in processed.files {
+        response = response.add_file(file);
+    }
+    message.channel_id.send_message(ctx, response).await?;
+    Ok(())
+}
+
+
+/// biggest file which gets downloaded, attachments and links alike
+const MAX_DOWNLOAD_BYTES: u64 = 16 * 1024 * 1024;
 
 async fn image_check(attachment: &Attachment) -> Result<()> {
-    let mib = attachment.size as f64 / 1024.0 / 1024.0;
-    if mib > 16.0 {
-        bail!("File too large: {} MiB", mib);
+    if attachment.size as u64 > MAX_DOWNLOAD_BYTES {
+        bail!("File too large: {} MiB", attachment.size as f64 / 1024.0 / 1024.0);
     }
-    if attachment.content_type.is_none() {
-        bail!("No content type found for attachment");
+    // discord sometimes leaves out the content type, the bytes get sniffed after the download then
+    if let Some(content_type) = &attachment.content_type {
+        if !content_type.starts_with("image/") {
+            bail!("Attachment is not an image: {}", content_type);
+        }
+        // AVIF for example needs a decoder feature, so this depends on the build
+        let readable = content_type == "image/svg+xml"
+            || image::ImageFormat::from_mime_type(content_type).is_some_and(|format| format.reading_enabled());
+        if !readable {
+            bail!("I can't read {} images. I can read {}", content_type, supported_formats().join(", "));
+        }
     }
-    let content_type = attachment.content_type.as_ref().unwrap();
-    if !content_type.starts_with("image/") {
-        bail!("Attachment is not an image: {}", content_type);
+    Ok(())
+}
+
+/// extensions of the formats which this build decodes
+fn supported_formats() -> Vec<&'static str> {
+    image::ImageFormat::all()
+        .filter(|format| format.reading_enabled())
+        .filter_map(|format| format.extensions_str().first().copied())
+        .chain(["svg"])
+        .collect()
+}
+
+/// reads only the header, so a small file which decodes to a gigantic image is rejected before it takes up memory
+fn check_dimensions(bytes: &[u8], max_megapixels: f32) -> Result<()> {
+    let (width, height) = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(|e| anyhow::anyhow!("Can't read the size of that image: {}", e))?;
+    let megapixels = width as f32 * height as f32 / 1_000_000.0;
+    if megapixels > max_megapixels {
+        bail!("That image is {width}x{height}, that's {megapixels:.0} megapixels. I only take up to {max_megapixels:.0}.");
     }
     Ok(())
 }
 
+/// checks the magic bytes, since the declared content type can be missing or wrong
+fn validate_image_bytes(bytes: &[u8]) -> Result<image::ImageFormat> {
+    image::guess_format(bytes).map_err(|_| anyhow::anyhow!("That file doesn't look like an image"))
+}
+
+
+/// like fetch_image_and_info for links, which have no attachment to cache them by
+pub async fn fetch_url_and_info(url: &str, data: &Data) -> Result<(DynamicImage, ImageInformation)> {
+    let config = data.config();
+    let max_dimension = config.limits.max_dimension;
+    let image = download_from_url(url, config.limits.max_megapixels).await?;
+    run_blocking(move || {
+        let image = colors::fit_within(image, max_dimension);
+        let info = colors::calculate_average_brightness(&image.to_rgba8());
+        Ok((image, info))
+    }).await
+}
 
 pub async fn fetch_image_and_info(attachment: &Attachment, data: &Data) -> Result<(DynamicImage, ImageInformation)> {
     image_check(attachment).await?;
@@ -387,10 +1180,14 @@ pub async fn fetch_image_and_info(attachment: &Attachment, data: &Data) -> Resul
     let image_and_info = {
         let image = data.image_cache.get(&url).await;
         if image.is_none() {
-            let image = download_image(&attachment).await?;
-            Ok::<(DynamicImage, ImageInformation), anyhow::Error>(
-                (image.clone(), colors::calculate_average_brightness(&image.to_rgba8()))
-            )
+            let config = data.config();
+            let max_dimension = config.limits.max_dimension;
+            let image = download_image(&attachment, config.limits.max_megapixels).await?;
+            run_blocking(move || {
+                let image = colors::fit_within(image, max_dimension);
+                let info = colors::calculate_average_brightness(&image.to_rgba8());
+                Ok((image, info))
+            }).await
         } else {
             Ok(image.unwrap())
         }
@@ -405,98 +1202,855 @@ async fn ask_user_to_darken_image(
     attachment: &Attachment, 
     data: &Data
 ) -> Result<(), anyhow::Error> {
+    // the commands keep working, only the unasked prompt is switched off
+    if !data.auto_prompt(message.guild_id) {
+        return Ok(());
+    }
 
     // download image or get from cache
     image_check(attachment).await?;
     let url = attachment.url.clone();
-    let image_and_info = fetch_image_and_info(attachment, data).await;
-    let (image, info) = image_and_info
-        .expect("Image or info is none in ask_user_to_darken_image");
-    println!("inserting");
+    let (image, info) = fetch_image_and_info(attachment, data).await
+        .context("Couldn't fetch the image for the prompt")?;
     data.image_cache.insert(url, (image.clone(), info.clone())).await;
     let bright = info.brightness.average;
-    if bright < data.config.threshold.brightness {
-        panic!("Not bright enough: {bright}")
-    }
+    let palette = palette_to_offer(&image, bright, message.guild_id, data)?;
     
     let start = std::time::Instant::now();
     let image_scale = brightnes_image::generate_image(bright, 1.0, 9.0);
     let mut buffer = Cursor::new(Vec::new()); // Use Cursor to add Seek capability
-    println!("Pre save {:?}", start.elapsed());
-    image_scale.write_to(&mut buffer, image::ImageFormat::WebP).expect("Failed to write image to buffer");
-    println!("After save {:?}", start.elapsed());
+    image_scale.write_to(&mut buffer, image::ImageFormat::WebP)?;
     // Optionally, reset cursor position to the beginning if you need to read from it afterward
     buffer.set_position(0);
-    let attachment = CreateAttachment::bytes(buffer.into_inner(), "scale.webp");
+    let scale = CreateAttachment::bytes(buffer.into_inner(), "scale.webp");
 
-    println!("Generated image in {:?}", start.elapsed());
-    let response = CreateMessage::new()
-        .content(
-            format!(
-                "Bruhh... This looks bright as fuck. On a scale **from 1 to 9 it's a {:.1}**.\nMay I darken it?", 
-                bright*8. + 1.)
-        )
-        .files(vec![attachment])
-        .button(CreateButton::new(
-            NordOptions::new().make_nord_custom_id(&message.id.into(), false, None)
-        )
+    debug!("Generated brightness scale elapsed_ms={}", start.elapsed().as_millis());
+    let texts = data.messages(message.guild_id);
+    let format = OutputFormat::for_content_type(attachment.content_type.as_deref());
+    let rows = prompt_rows(message.id, palette, format, &texts, false);
+    let response = if palette.is_light() {
+        CreateMessage::new().content(fill(&texts.ask_to_lighten, "palette", palette.name()))
+    } else if is_spoiler(&attachment.filename) {
+        CreateMessage::new().content(&texts.ask_to_darken_spoiler)
+    } else {
+        // spoilers don't get this, the colors would give the image away
+        let dominant: Vec<RgbColor> = colors::dominant_colors(&image.to_rgba8(), DOMINANT_COLORS)
+            .into_iter()
+            .map(|(color, _share)| color)
+            .collect();
+        let hex_codes = dominant.iter().map(|color| format!("`{}`", color.to_hex())).collect::<Vec<_>>().join(" ");
+        let analysis = fill(&fill(&texts.analysis, "colors", hex_codes), "brightness", format!("{bright:.2}"));
+        let strip = encode::encode_image(&swatches::render_color_strip(&dominant), OutputFormat::WebP)?;
+        CreateMessage::new()
+            .content(format!(
+                "{}\n{analysis}", fill(&texts.ask_to_darken, "scale", format!("{:.1}", bright*8. + 1.))
+            ))
+            .files(vec![scale, CreateAttachment::bytes(strip, "colors.webp")])
+    };
+    let response = response.components(rows);
+    let mut prompt = message.channel_id.send_message(&ctx, response).await?;
+    data.prompts.lock().unwrap().insert(prompt.id, Instant::now());
+
+    // disables the buttons, unless someone answered the prompt in time
+    let timeout = Duration::from_secs(data.config().prompt.expire_secs);
+    let (ctx, prompts, original_id) = (ctx.clone(), data.prompts.clone(), message.id);
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        if prompts.lock().unwrap().remove(&prompt.id).is_none() {
+            return;
+        }
+        let edit = EditMessage::new().components(prompt_rows(original_id, palette, format, &texts, true));
+        if let Err(e) = prompt.edit(&ctx, edit).await {
+            warn!("Failed to disable expired prompt: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// the palette a prompt offers for the image, errors for images which get no prompt
+fn palette_to_offer(image: &DynamicImage, bright: f32, guild_id: Option<GuildId>, data: &Data) -> Result<Palette> {
+    let threshold = data.brightness_threshold(guild_id);
+    let palette = match data.prompt_palette(guild_id) {
+        // very dark images get the light version of Nord, the images in between no prompt at all
+        palette if !palette.is_light() && bright < data.config().prompt.lighten_below => Palette::NordLight,
+        palette => palette,
+    };
+    // light palettes are offered for dark images, the threshold counts from the other end
+    if palette.is_light() {
+        if bright > 1.0 - threshold {
+            bail!("Not dark enough brightness={bright:.3} threshold={:.3}", 1.0 - threshold);
+        }
+    } else if bright < threshold {
+        bail!("Not bright enough brightness={bright:.3} threshold={threshold:.3}");
+    }
+    if colors::is_already_converted(&image.to_rgba8(), palette) {
+        bail!("Already in the {} palette", palette.name());
+    }
+    Ok(palette)
+}
+
+/// how many colors the analysis below a prompt shows
+const DOMINANT_COLORS: usize = 5;
+
+/// the 🌙 (☀️ for light palettes) and the no button, then a row with the other ways to convert
+fn prompt_rows(message_id: MessageId, palette: Palette, format: OutputFormat, texts: &Messages, disabled: bool) -> Vec<CreateActionRow> {
+    let options = NordOptions { palette, format, ..NordOptions::new() };
+    let emoji = if palette.is_light() { "☀️" } else { "🌙" };
+    let variants = [
+        (NordOptions { dither: Dither::FloydSteinberg, ..options.clone() }, &texts.dithered_button),
+        (NordOptions { preserve_luminance: true, ..options.clone() }, &texts.tint_button),
+        (NordOptions { gradient_map: true, ..options.clone() }, &texts.gradient_button),
+        (NordOptions { bright_only: true, ..options.clone() }, &texts.bright_only_button),
+    ];
+    let answers = vec![
+        CreateButton::new(options.make_nord_custom_id(&message_id.into(), false, None))
             .style(ButtonStyle::Primary)
-            .emoji("🌙".parse::<ReactionType>().unwrap())
-        )
-        .button(CreateButton::new(format!("stop-{}", message.id))
+            .emoji(emoji.parse::<ReactionType>().unwrap())
+            .disabled(disabled),
+        CreateButton::new(format!("stop-{}", message_id))
             .style(ButtonStyle::Primary)
-            .label("No")
-        );
-    let _new_message = message.channel_id.send_message(&ctx, response).await?;
-    Ok(())
+            .label(&texts.no_button)
+            .disabled(disabled),
+    ];
+    let variants = variants
+        .iter()
+        .map(|(options, label)| {
+            CreateButton::new(options.make_nord_custom_id(&message_id.into(), false, None))
+                .style(ButtonStyle::Secondary)
+                .label(label.as_str())
+                .disabled(disabled)
+        })
+        .collect();
+    vec![CreateActionRow::Buttons(answers), CreateActionRow::Buttons(variants)]
+}
+
+/// prompts which nobody answered for too long don't convert anymore
+fn prompt_expired(created: Instant, now: Instant, timeout: Duration) -> bool {
+    now.saturating_duration_since(created) >= timeout
+}
+
+
+#[derive(Clone, Debug)]
+pub enum Progress {
+    Downloading,
+    Converting,
+    /// done frames and the total number of frames
+    Frame(usize, usize),
+}
+
+impl Progress {
+    fn describe(&self, texts: &Messages) -> String {
+        match self {
+            Progress::Downloading => texts.downloading.clone(),
+            Progress::Converting => texts.converting.clone(),
+            Progress::Frame(done, total) => fill(&fill(&texts.converting_frames, "done", done), "total", total),
+        }
+    }
 }
 
+/// gets called between the steps of a conversion, so it has to return quickly
+pub type ProgressCallback<'a> = &'a (dyn Fn(Progress) + Send + Sync);
 
-async fn fetch_image(
+pub enum ProcessedImage {
+    Still(DynamicImage),
+    /// the conversion and the side by side comparison with the original
+    Compared(DynamicImage, DynamicImage),
+    /// with a note like "kept 100 of 240 frames" when the limits changed the animation
+    Animation(Animation, Option<String>),
+}
+
+/// applies the frame and size limits of the config, the note tells what had to change.
+/// Too long animations without skip_frames were already rejected while decoding
+fn limit_animation(animation: Animation, limits: &config::AnimationConfig) -> (Animation, Option<String>) {
+    let total_frames = animation.source_frames;
+    let size = animation.dimensions();
+    let animation = animation.skip_frames(limits.max_frames).fit_within(limits.max_width, limits.max_height);
+    let mut changes = Vec::new();
+    if animation.frames.len() < total_frames {
+        changes.push(format!("kept {} of {total_frames} frames", animation.frames.len()));
+    }
+    let (width, height) = animation.dimensions();
+    if (width, height) != size {
+        changes.push(format!("scaled to {width}×{height}"));
+    }
+    let note = (!changes.is_empty()).then(|| changes.join(", "));
+    (animation, note)
+}
+
+/// what /nord adds to the options which the buttons carry
+#[derive(Default)]
+pub struct ConversionExtras {
+    /// registered palettes and color groups, instead of the palette of the options
+    pub colors: Option<PaletteColors>,
+    /// what the conversion is counted as, instead of the palette of the options
+    pub applied: Option<String>,
+    pub saturation: Option<f32>,
+    /// replaces colors::SMOOTH_RADIUS when the options smooth
+    pub smooth_radius: Option<u32>,
+    pub posterize: Option<u16>,
+    pub scale: Option<f32>,
+    /// repeats the result to fill this size
+    pub tile: Option<(u32, u32)>,
+}
+
+impl ConversionExtras {
+    fn palette_colors(&self, options: &NordOptions, guild_id: Option<GuildId>, data: &Data) -> PaletteColors {
+        self.colors.clone().unwrap_or_else(|| data.palette_colors(options.palette, guild_id))
+    }
+
+    fn applied(&self, options: &NordOptions) -> String {
+        // dimmed images keep their own colors
+        if options.dim {
+            return String::from("dim");
+        }
+        self.applied.clone().unwrap_or_else(|| options.palette.as_str().to_string())
+    }
+}
+
+/// the palette mapping of a still image or of a single frame
+fn convert_frame(
+    image: DynamicImage, 
+    options: &NordOptions, 
+    info: &ImageInformation, 
+    palette_colors: &PaletteColors, 
+    dim_target: f32
+) -> DynamicImage {
+    if options.dim {
+        colors::dim_to_brightness(image, dim_target)
+    } else {
+        colors::apply_nord_with_colors(image, options.clone(), info, palette_colors)
+    }
+}
+
+async fn process_image(
     attachment: &serenity::Attachment, 
+    guild_id: Option<GuildId>, 
     data: &Data, 
-) -> (DynamicImage, ImageInformation) {
-    image_check(attachment).await.unwrap();
-    let url = attachment.url.clone();
-    let image_and_info = {
-        let image = data.image_cache.get(&url).await;
-        if image.is_none() {
-            let image = download_image(&attachment).await.unwrap();
-            Ok::<(DynamicImage, ImageInformation), anyhow::Error>(
-                (image.clone(), colors::calculate_average_brightness(&image.to_rgba8()))
-            )
-        } else {
-            Ok(image.unwrap())
+    options: colors::NordOptions,
+    extras: ConversionExtras,
+    progress: ProgressCallback<'_>
+) -> Result<ProcessedImage> {
+    progress(Progress::Downloading);
+    let (image, info) = fetch_image_and_info(attachment, data).await
+        .context("Sorry, I couldn't grab that image.")?;
+    // the config can be reloaded, so the ramp isn't part of the buttons
+    let options = NordOptions { gradient_ramp: data.config().gradient.ramp.clone(), ..options };
+    let decode_animation = match attachment.content_type.as_deref() {
+        Some("image/gif") => Some(Animation::decode_gif as fn(&[u8], usize, bool) -> Result<Animation>),
+        // Discord's "GIFs" are often animated WebPs
+        Some("image/webp") => Some(Animation::decode_webp as fn(&[u8], usize, bool) -> Result<Animation>),
+        _ => None,
+    };
+    if let Some(decode_animation) = decode_animation {
+        // the proxy url only delivers the first frame
+        let bytes = download_bytes(&attachment.url).await?;
+        check_dimensions(&bytes, data.config().limits.max_megapixels)?;
+        let (max_frames, skip_frames) = (data.config().animation.max_frames, data.config().animation.skip_frames);
+        let animation = run_blocking(move || decode_animation(&bytes, max_frames, skip_frames)).await?;
+        if animation.frames.len() > 1 {
+            let (animation, note) = limit_animation(animation, &data.config().animation);
+            debug!("Processing animation frames={}", animation.frames.len());
+            let palette_colors = extras.palette_colors(&options, guild_id, data);
+            let applied = extras.applied(&options);
+            let dim_target = data.brightness_threshold(guild_id);
+            // the progress callback is borrowed, so the blocking task sends the done frames back here
+            let (frame_sender, mut frames_done) = tokio::sync::mpsc::unbounded_channel();
+            let converting = run_blocking(move || Ok(animation.map_frames(
+                |frame| convert_frame(frame, &options, &info, &palette_colors, dim_target),
+                move |done, total| { let _ = frame_sender.send((done, total)); }
+            )));
+            tokio::pin!(converting);
+            let animation = loop {
+                tokio::select! {
+                    Some((done, total)) = frames_done.recv() => progress(Progress::Frame(done, total)),
+                    animation = &mut converting => break animation?,
+                }
+            };
+            data.conversions.record(&applied);
+            return Ok(ProcessedImage::Animation(animation, note));
+        }
+    }
+    convert_image(image, info, guild_id, data, options, extras, progress).await
+}
+
+/// every pixel pass of a still image, all of them on a blocking thread
+async fn convert_image(
+    image: DynamicImage,
+    info: ImageInformation,
+    guild_id: Option<GuildId>, 
+    data: &Data, 
+    options: colors::NordOptions,
+    extras: ConversionExtras,
+    progress: ProgressCallback<'_>
+) -> Result<ProcessedImage> {
+    let options = NordOptions { gradient_ramp: data.config().gradient.ramp.clone(), ..options };
+    let palette_colors = extras.palette_colors(&options, guild_id, data);
+    let applied = extras.applied(&options);
+    let dim_target = data.brightness_threshold(guild_id);
+    let (clip, max_dimension) = (data.config().contrast.clip_percent, data.config().limits.max_dimension);
+    let smooth_radius = options.smooth.then(|| extras.smooth_radius.unwrap_or(colors::SMOOTH_RADIUS));
+    let ConversionExtras { saturation, posterize, scale, tile, .. } = extras;
+    progress(Progress::Converting);
+    let processed = run_blocking(move || {
+        let image = match smooth_radius {
+            Some(radius) => colors::smooth_noise(image, radius),
+            None => image,
+        };
+        let image = if options.auto_contrast { colors::auto_contrast(image, clip, clip) } else { image };
+        let image = match saturation {
+            Some(amount) => colors::boost_saturation(image, amount),
+            None => image,
+        };
+        let image = match posterize {
+            Some(levels) => colors::posterize(image, levels),
+            None => image,
+        };
+        let original = options.compare.then(|| image.clone());
+        let converted = convert_frame(image, &options, &info, &palette_colors, dim_target);
+        let comparison = original.map(|original| comparison::make_comparison(&original, &converted, options.palette));
+        let converted = match scale {
+            Some(scale) => colors::scale_output(converted, scale, max_dimension),
+            None => converted,
+        };
+        let converted = match tile {
+            Some((width, height)) => colors::tile_to(&converted, width, height),
+            None => converted,
+        };
+        Ok(match comparison {
+            Some(comparison) => ProcessedImage::Compared(converted, comparison),
+            None => ProcessedImage::Still(converted),
+        })
+    }).await;
+    data.conversions.record_result(&applied, processed)
+}
+
+/// stats of a still image, the comparison and the result, both encoded with their format
+pub type EncodedImage = (Option<ConversionStats>, Option<(Vec<u8>, OutputFormat)>, (Vec<u8>, &'static str));
+
+/// encodes the result within the upload limit, animations become GIFs
+async fn encode_processed(
+    image: ProcessedImage, 
+    format: OutputFormat, 
+    encode_options: encode::EncodeOptions, 
+    max_upload_bytes: usize
+) -> Result<EncodedImage> {
+    run_blocking(move || Ok(match image {
+        ProcessedImage::Still(image) => {
+            let stats = ConversionStats::from_image(&image.to_rgba8());
+            let (buffer, format) = encode::encode_within_budget(&image, format, encode_options, max_upload_bytes)?;
+            (Some(stats), None, (buffer, format.extension()))
+        },
+        ProcessedImage::Compared(image, comparison) => {
+            let stats = ConversionStats::from_image(&image.to_rgba8());
+            let comparison = encode::encode_within_budget(&comparison, format, encode_options, max_upload_bytes)?;
+            let (buffer, format) = encode::encode_within_budget(&image, format, encode_options, max_upload_bytes)?;
+            (Some(stats), Some(comparison), (buffer, format.extension()))
+        },
+        ProcessedImage::Animation(animation, _) => (None, None, (animation.encode_gif()?, "gif")),
+    })).await
+}
+
+async fn convert_gif_link(
+    ctx: &SContext,
+    message: &Message,
+    link: &links::GifLink,
+    data: &Data
+) -> Result<()> {
+    let url = match links::resolve_media_url(link).await {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("Failed to resolve link url={} reason={}", link.url, e);
+            // fall back to the preview discord generated
+            match links::embed_media_url(message, link) {
+                Some(url) => url,
+                None => return Err(e),
+            }
         }
     };
-    image_and_info.unwrap()
+    if links::is_video_url(&url) {
+        bail!("{} is only available as video, which I can't decode", link.url);
+    }
+    let content = data.messages(message.guild_id).linked_gif_done;
+    convert_linked_image(ctx, message, &url, &content, data).await
 }
 
+/// converts the image behind the url and replies with it, if an attachment like it would get a prompt.
+/// Links have no attachment for the buttons to refer to, so the conversion is sent right away
+async fn convert_linked_image(
+    ctx: &SContext,
+    message: &Message,
+    url: &str,
+    content: &str,
+    data: &Data
+) -> Result<()> {
+    // the server switched unasked conversions off, just like the prompt
+    if !data.auto_prompt(message.guild_id) {
+        return Ok(());
+    }
+    // anyone can post links, so they count against the limit before anything is downloaded
+    if data.rate_limiter.is_rate_limited(message.author.id, Instant::now()) {
+        debug!("Rate limited linked image user={} url={}", message.author.id, url);
+        return Ok(());
+    }
+    let (image, info) = fetch_url_and_info(url, data).await?;
+    let palette = match palette_to_offer(&image, info.brightness.average, message.guild_id, data) {
+        Ok(palette) => palette,
+        Err(e) => {
+            debug!("No conversion for linked image url={} reason={}", url, e);
+            return Ok(());
+        }
+    };
+    let mut options = NordOptions::from_image_information(&info);
+    options.palette = palette;
+    options.start = true;
+    let palette_colors = data.palette_colors(palette, message.guild_id);
+    let _permit = data.queue.acquire().await;
+    let buffer = run_blocking(move || {
+        let image = colors::apply_nord_with_colors(image, options, &info, &palette_colors);
+        encode::encode_image(&image, OutputFormat::WebP)
+    }).await?;
+    data.conversions.record(palette.as_str());
 
-async fn process_image(attachment: &serenity::Attachment, data: &Data, options: colors::NordOptions) -> Result<DynamicImage> {
-    let (image, info) = fetch_image(attachment, data).await;
-    Ok(colors::apply_nord(image, options, &info))
+    let attachment = CreateAttachment::bytes(buffer, "image.webp");
+    let response = CreateMessage::new()
+        .content(content)
+        .add_file(attachment)
+        .reference_message(message);
+    message.channel_id.send_message(&ctx, response).await?;
+    Ok(())
 }
 
-async fn download_image(attachment: &Attachment) -> Result<DynamicImage> {
-    // Send the GET request
+async fn download_image(attachment: &Attachment, max_megapixels: f32) -> Result<DynamicImage> {
+    // the proxy drops the EXIF data, which is needed for the orientation of photos,
+    // WebP and AVIF are decoded from the original file so lossless alpha stays intact,
+    // SVGs are rasterized by us
+    if matches!(attachment.content_type.as_deref(), Some("image/jpeg" | "image/tiff" | "image/webp" | "image/avif" | "image/svg+xml")) {
+        return download_from_url(&attachment.url, max_megapixels).await;
+    }
     //println!("Downloading: {}=&format=png", attachment.proxy_url);
-    let response = reqwest::get(format!("{}=&format=png", attachment.proxy_url)).await?;
-    
-    // Ensure the request was successful
-    if !response.status().is_success() {
-        info!("Request failed with status code: {}", response.status());
-        anyhow::bail!("Request failed with status code: {}", response.status());
+    download_from_url(&format!("{}=&format=png", attachment.proxy_url), max_megapixels).await
+}
+
+async fn download_from_url(url: &str, max_megapixels: f32) -> Result<DynamicImage> {
+    let bytes = download_bytes(url).await?;
+    run_blocking(move || decode_image(&bytes, max_megapixels)).await
+}
+
+/// runs CPU heavy work on tokio's blocking threads, so heartbeats and other events aren't stalled meanwhile
+async fn run_blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work).await
+        .context("The image processing stopped unexpectedly")?
+}
+
+fn decode_image(bytes: &[u8], max_megapixels: f32) -> Result<DynamicImage> {
+    if svg::is_svg(bytes) {
+        return svg::rasterize(bytes);
     }
-   
-    let bytes = response.bytes().await?;
-    // let raw = attachment.download().await?;
-    // Get the image bytes
-    println!("Downloaded image with {} bytes", bytes.len());
+    let format = validate_image_bytes(bytes)?;
+    check_dimensions(bytes, max_megapixels)?;
     // Load the image from the bytes
-    let image = image::load_from_memory(&bytes).map_err(
+    let image = image::load_from_memory_with_format(bytes, format).map_err(
         |e| anyhow::anyhow!("Failed to load image: {}", e)
     )?;
-    
+    // only JPEGs and TIFFs carry an orientation we read, everything else stays untouched
+    let image = match exif::read_orientation(bytes) {
+        Some(orientation) => exif::apply_orientation(image, orientation),
+        None => image,
+    };
     Ok(image)
-}
\ No newline at end of file
+}
+
+async fn download_bytes(url: &str) -> Result<Vec<u8>> {
+    const ATTEMPTS: u32 = 3;
+    let mut last_error = anyhow::anyhow!("No download attempt made");
+    for attempt in 1..=ATTEMPTS {
+        if attempt > 1 {
+            // 250ms, 500ms, ... plus up to 100ms jitter so retries don't line up
+            let jitter = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis() % 100;
+            let backoff = 250 * 2u64.pow(attempt - 2) + jitter as u64;
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+        }
+        match try_download_bytes(url).await {
+            Ok(bytes) => return Ok(bytes),
+            Err(DownloadError::Permanent(e)) => return Err(e),
+            Err(DownloadError::Transient(e)) => {
+                warn!("Download attempt {attempt}/{ATTEMPTS} of {url} failed: {e}");
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error.context(format!("Download failed after {ATTEMPTS} attempts")))
+}
+
+enum DownloadError {
+    /// timeouts, connection problems and 5xx responses, worth another try
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+async fn try_download_bytes(url: &str) -> Result<Vec<u8>, DownloadError> {
+    let transient_or_permanent = |e: reqwest::Error| {
+        if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() {
+            DownloadError::Transient(e.into())
+        } else {
+            DownloadError::Permanent(e.into())
+        }
+    };
+    // Send the GET request
+    let mut response = reqwest::get(url).await.map_err(transient_or_permanent)?;
+    
+    // Ensure the request was successful
+    let status = response.status();
+    if !status.is_success() {
+        warn!("Download failed url={} status={}", url, status);
+        let e = anyhow::anyhow!("Request failed with status code: {}", status);
+        return Err(if status.is_server_error() { DownloadError::Transient(e) } else { DownloadError::Permanent(e) });
+    }
+   
+    let too_large = || DownloadError::Permanent(anyhow::anyhow!(
+        "File too large: more than {} MiB", MAX_DOWNLOAD_BYTES / 1024 / 1024
+    ));
+    if response.content_length().is_some_and(|length| length > MAX_DOWNLOAD_BYTES) {
+        return Err(too_large());
+    }
+    // the length can be missing or wrong, so the body is read in chunks and cut off at the limit
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(transient_or_permanent)? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_DOWNLOAD_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    debug!("Downloaded url={} bytes={}", url, bytes.len());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// answers the connections in order with the given status and body, like a flaky file host
+    async fn serve(responses: Vec<(u16, Vec<u8>)>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let head = format!("HTTP/1.1 {status} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                // the client may hang up early, like on bodies which are too large
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            }
+        });
+        format!("http://{address}/image.png")
+    }
+
+    #[tokio::test]
+    async fn failed_downloads_are_errors() {
+        let url = serve(vec![(404, Vec::new())]).await;
+        assert!(download_from_url(&url, 40.0).await.is_err());
+        let url = serve(vec![(200, b"<html>not an image</html>".to_vec())]).await;
+        assert!(download_from_url(&url, 40.0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_downloads_are_refused() {
+        let url = serve(vec![(200, vec![0; MAX_DOWNLOAD_BYTES as usize + 1])]).await;
+        let error = download_from_url(&url, 40.0).await.unwrap_err();
+        assert!(error.to_string().contains("too large"), "{error:#}");
+    }
+
+    #[test]
+    fn sniffs_images_without_a_content_type() {
+        let files: [(&[u8], image::ImageFormat); 4] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", image::ImageFormat::Png),
+            (b"\xFF\xD8\xFF\xE0\0\x10JFIF\0", image::ImageFormat::Jpeg),
+            (b"GIF89a\x01\0\x01\0", image::ImageFormat::Gif),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", image::ImageFormat::WebP),
+        ];
+        for (bytes, format) in files {
+            assert_eq!(validate_image_bytes(bytes).unwrap(), format);
+        }
+        assert!(validate_image_bytes(b"<html>not an image</html>").is_err());
+    }
+
+    #[test]
+    fn repeated_conversions_come_from_the_cache() {
+        let cache = ResultCache::new(4, Duration::from_secs(60), 1024);
+        let key = "1024|darken-nord".to_string();
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), (vec![1, 2, 3], "webp"));
+
+        assert_eq!(cache.get(&key), Some((vec![1, 2, 3], "webp")));
+        assert!(cache.get("1024|darken-gruvbox").is_none());
+        assert_eq!(cache.hits_and_misses(), (1, 2));
+    }
+
+    #[test]
+    fn cached_results_expire() {
+        let cache = ResultCache::new(4, Duration::from_millis(20), 1024);
+        cache.insert("image".to_string(), (vec![1, 2, 3], "webp"));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(cache.get("image").is_none());
+    }
+
+    fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::new_rgba8(width, height).write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[tokio::test]
+    async fn downloads_are_retried_after_server_errors() {
+        let url = serve(vec![(500, Vec::new()), (503, Vec::new()), (200, png_bytes(8, 6))]).await;
+        let image = download_from_url(&url, 40.0).await.unwrap();
+        assert_eq!((image.width(), image.height()), (8, 6));
+
+        let url = serve(vec![(500, Vec::new()), (500, Vec::new()), (500, Vec::new())]).await;
+        let error = download_from_url(&url, 40.0).await.unwrap_err();
+        assert!(format!("{error:#}").contains("after 3 attempts"));
+    }
+
+    /// the state the bot starts with, without a discord connection
+    fn test_data() -> Data {
+        let config = config::load_config();
+        Data {
+            image_cache: Arc::new(ImageCache::new()),
+            result_cache: ResultCache::new(4, Duration::from_secs(60), 1024 * 1024),
+            queue: ConversionQueue::new(1),
+            config: RwLock::new(Arc::new(config)),
+            guild_thresholds: Mutex::new(HashMap::new()),
+            guild_compression: Mutex::new(HashMap::new()),
+            guild_palettes: Mutex::new(HashMap::new()),
+            user_strengths: Mutex::new(HashMap::new()),
+            auto_prompt_disabled: Mutex::new(HashSet::new()),
+            custom_palettes: Mutex::new(HashMap::new()),
+            palettes: RwLock::new(Arc::new(PaletteRegistry::builtin())),
+            rate_limiter: RateLimiter::from_env(),
+            moon_conversions: Mutex::new(HashSet::new()),
+            seen_attachments: Mutex::new(TtlCache::new(10)),
+            originals: OriginalStore::new(4, originals::RESTORE_WINDOW),
+            prompts: Arc::new(Mutex::new(HashMap::new())),
+            conversions: Arc::new(ConversionCounter::new()),
+            locales: RwLock::new(Arc::new(messages::load_locales(&messages::locales_dir()))),
+            guild_locales: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn direct_messages_use_the_defaults() {
+        let data = test_data();
+        let guild = GuildId::new(1);
+        data.guild_thresholds.lock().unwrap().insert(guild, 0.2);
+        data.guild_palettes.lock().unwrap().insert(guild, Palette::Gruvbox);
+        data.custom_palettes.lock().unwrap().insert(guild, vec![[0, 0, 0], [128, 128, 128], [255, 255, 255]]);
+
+        assert_eq!(data.brightness_threshold(Some(guild)), 0.2);
+        assert_eq!(data.brightness_threshold(None), config::load_config().threshold.brightness);
+        assert_eq!(data.prompt_palette(Some(guild)), Palette::Gruvbox);
+        assert_eq!(data.prompt_palette(None), Palette::Nord);
+        // without a guild there is no custom palette, Nord stands in
+        assert_eq!(data.palette_swatches(Palette::Custom, None), Palette::Nord.colors());
+        assert_eq!(data.palette_swatches(Palette::Custom, Some(guild)).len(), 3);
+    }
+
+    #[test]
+    fn transparent_webps_decode_and_convert() {
+        let image = RgbaImage::from_fn(16, 8, |x, y| Rgba([x as u8 * 16, 200, y as u8 * 32, if x < 8 { 255 } else { 0 }]));
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image.clone()).write_to(&mut bytes, image::ImageFormat::WebP).unwrap();
+
+        let decoded = decode_image(bytes.get_ref(), 40.0).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 8));
+        // lossless, so the alpha comes back exactly
+        assert_eq!(decoded.to_rgba8(), image);
+
+        let palette: Vec<[u8; 3]> = Palette::Nord.colors().iter().map(|color| {
+            let [r, g, b, _] = color.to_rgba().0;
+            [r, g, b]
+        }).collect();
+        let converted = colors::apply_palette(decoded, &palette, colors::DistanceMetric::RgbEuclidean, Dither::FloydSteinberg, false, false).to_rgba8();
+        for (converted, original) in converted.pixels().zip(image.pixels()) {
+            assert_eq!(converted[3], original[3]);
+            if original[3] > 0 {
+                assert!(palette.contains(&[converted[0], converted[1], converted[2]]), "{converted:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn avif_is_recognized_and_readable() {
+        let header = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+        assert_eq!(validate_image_bytes(header).unwrap(), image::ImageFormat::Avif);
+        assert!(image::ImageFormat::Avif.reading_enabled());
+        assert!(supported_formats().contains(&"avif"));
+    }
+
+    #[test]
+    fn reloads_never_show_half_a_config() {
+        let data = Arc::new(test_data());
+        let config = |brightness: f32, max_dimension: u32| {
+            let mut config = config::load_config();
+            config.threshold.brightness = brightness;
+            config.limits.max_dimension = max_dimension;
+            config
+        };
+        data.reload(config(0.4, 1000), HashMap::from([(messages::DEFAULT_LOCALE.to_string(), Messages::default())]), PaletteRegistry::builtin());
+
+        let reader = {
+            let data = data.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10_000 {
+                    let config = data.config();
+                    let pair = (config.threshold.brightness, config.limits.max_dimension);
+                    assert!(pair == (0.4, 1000) || pair == (0.6, 2000), "{pair:?}");
+                }
+            })
+        };
+        for i in 0..1_000 {
+            let (brightness, max_dimension) = if i % 2 == 0 { (0.6, 2000) } else { (0.4, 1000) };
+            data.reload(config(brightness, max_dimension), HashMap::from([(messages::DEFAULT_LOCALE.to_string(), Messages::default())]), PaletteRegistry::builtin());
+        }
+        reader.join().unwrap();
+
+        let config = data.config();
+        assert_eq!((config.threshold.brightness, config.limits.max_dimension), (0.4, 1000));
+    }
+
+    #[test]
+    fn spoilers_stay_spoilers() {
+        assert!(is_spoiler("SPOILER_cat.png"));
+        assert!(!is_spoiler("cat.png"));
+        assert!(!is_spoiler("spoiler_cat.png"));
+
+        assert_eq!(output_filename("SPOILER_cat.png", "", "webp"), "SPOILER_cat.webp");
+        assert_eq!(output_filename("SPOILER_cat.png", "-comparison", "png"), "SPOILER_cat-comparison.png");
+        assert_eq!(output_filename("cat.photo.jpg", "", "webp"), "cat.photo.webp");
+        assert_eq!(output_filename("cat", "", "gif"), "cat.gif");
+    }
+
+    #[test]
+    fn prompts_expire_after_the_timeout() {
+        let created = Instant::now();
+        let timeout = Duration::from_secs(600);
+        assert!(!prompt_expired(created, created, timeout));
+        assert!(!prompt_expired(created, created + Duration::from_secs(599), timeout));
+        assert!(prompt_expired(created, created + timeout, timeout));
+        assert!(prompt_expired(created, created + Duration::from_secs(3600), timeout));
+        // a clock which went backwards doesn't expire anything
+        assert!(!prompt_expired(created + Duration::from_secs(10), created, timeout));
+    }
+
+    /// keeps every record, tests filter out the ones of other tests running at the same time
+    struct TestLogger(Mutex<Vec<(log::Level, String)>>);
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+
+    #[test]
+    fn conversions_are_logged_once_with_their_fields() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        let image = image::RgbaImage::new(640, 480);
+        log_conversion(UserId::new(424242), &[ConversionStats::from_image(&image)], Palette::Gruvbox, Duration::from_millis(1500));
+
+        let records: Vec<(log::Level, String)> = LOGGER.0.lock().unwrap()
+            .iter()
+            .filter(|(_, message)| message.contains("user=424242"))
+            .cloned()
+            .collect();
+        assert_eq!(records.len(), 1);
+        let (level, message) = &records[0];
+        assert_eq!(*level, log::Level::Info);
+        assert!(message.starts_with("converted "));
+        let palette = format!("palette={}", Palette::Gruvbox.as_str());
+        for field in ["width=640", "height=480", palette.as_str(), "elapsed_ms=1500"] {
+            assert!(message.contains(field), "{message}");
+        }
+    }
+
+    /// signature, IHDR of a 8000x6000 RGBA image and an empty IDAT, no pixel data at all
+    const HUGE_PNG_HEADER: [u8; 45] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a,
+        0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x1f, 0x40, 0x00, 0x00, 0x17, 0x70,
+        0x08, 0x06, 0x00, 0x00, 0x00, 0x31, 0x64, 0xe4, 0x3e,
+        0x00, 0x00, 0x00, 0x00, 0x49, 0x44, 0x41, 0x54, 0x35, 0xaf, 0x06, 0x1e,
+    ];
+
+    #[test]
+    fn oversized_images_are_rejected_by_their_header() {
+        // the header alone is enough to tell the size
+        assert!(check_dimensions(&HUGE_PNG_HEADER, 50.0).is_ok());
+        let error = check_dimensions(&HUGE_PNG_HEADER, 40.0).unwrap_err();
+        assert!(error.to_string().contains("8000x6000"), "{error}");
+        let error = decode_image(&HUGE_PNG_HEADER, 40.0).unwrap_err();
+        assert!(error.to_string().contains("8000x6000"), "{error}");
+    }
+
+    #[test]
+    fn disabled_guilds_get_no_prompt() {
+        let data = test_data();
+        let (quiet, other) = (GuildId::new(1), GuildId::new(2));
+        assert!(data.auto_prompt(Some(quiet)));
+        data.auto_prompt_disabled.lock().unwrap().insert(quiet);
+        assert!(!data.auto_prompt(Some(quiet)));
+        assert!(data.auto_prompt(Some(other)));
+        assert!(data.auto_prompt(None));
+    }
+
+    fn attachment(id: u64, filename: &str, content_type: &str, url: &str) -> Attachment {
+        ::serenity::json::from_str(&format!(
+            r#"{{"id": "{id}", "filename": "{filename}", "size": 1024, "url": "{url}", "proxy_url": "{url}", "content_type": "{content_type}"}}"#
+        )).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_converted_images_are_counted() {
+        let url = serve(vec![(200, png_bytes(8, 6))]).await;
+        let mut message = Message::default();
+        message.attachments = vec![
+            attachment(1, "photo.png", "image/png", &url),
+            attachment(2, "notes.pdf", "application/pdf", &url),
+            attachment(3, "slides.pdf", "application/pdf", &url),
+        ];
+        let data = test_data();
+        let processed = process_attachments(&message, None, &data, &NordOptions::default(), &|_| {}).await.unwrap();
+        // the pdfs are skipped, but they were never images to convert
+        assert_eq!(processed.converted, 1);
+        assert_eq!(processed.skipped.len(), 2);
+        assert_eq!(processed.files.len(), 1);
+    }
+
+    #[test]
+    fn cache_keys_outlive_refreshed_urls() {
+        let options = NordOptions::default();
+        let before = attachment(7, "photo.png", "image/png", "https://media.discordapp.net/photo.png?ex=1&hm=a");
+        let after = attachment(7, "photo.png", "image/png", "https://media.discordapp.net/photo.png?ex=2&hm=b");
+        assert_eq!(ResultCache::key(&before, &options), ResultCache::key(&after, &options));
+
+        let other = attachment(8, "photo.png", "image/png", "https://media.discordapp.net/photo.png?ex=1&hm=a");
+        assert_ne!(ResultCache::key(&before, &options), ResultCache::key(&other, &options));
+    }
+
+    #[test]
+    fn progress_is_described_in_the_language_of_the_guild() {
+        let texts = Messages { converting_frames: String::from("Bild {done} von {total}"), ..Messages::default() };
+        assert_eq!(Progress::Frame(3, 12).describe(&texts), "Bild 3 von 12");
+        assert_eq!(Progress::Downloading.describe(&texts), Messages::default().downloading);
+    }
+}
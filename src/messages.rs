@@ -0,0 +1,197 @@
+use std::{collections::HashMap, env, fs, path::{Path, PathBuf}};
+
+use log::warn;
+use serde::Deserialize;
+
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// every text the bot sends on its own, placeholders like `{scale}` get replaced
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Messages {
+    /// `{scale}` is the brightness from 1 to 9
+    pub ask_to_darken: String,
+    /// asked instead for spoilers, without the scale since that already tells something about the image
+    pub ask_to_darken_spoiler: String,
+    /// below the prompt, `{colors}` are the hex codes of the main colors and `{brightness}` the average brightness
+    pub analysis: String,
+    /// asked for dark images when the server picked a light palette, `{palette}` is its name
+    pub ask_to_lighten: String,
+    pub no_button: String,
+    pub dithered_button: String,
+    pub tint_button: String,
+    pub gradient_button: String,
+    pub bright_only_button: String,
+    pub prompt_expired: String,
+    /// buttons of older versions carry options which can't be read anymore
+    pub outdated_button: String,
+    pub working: String,
+    pub changing_options: String,
+    pub options_changed: String,
+    pub image_vanished: String,
+    pub rate_limited: String,
+    /// `{position}` is the place in line
+    pub in_line: String,
+    pub cancel_button: String,
+    pub cancelled: String,
+    pub already_started: String,
+    pub done: String,
+    /// `{palette}` is the name of the palette of the result
+    pub palette_used: String,
+    pub disposed: String,
+    /// `{minutes}` is how long the original can be restored
+    pub restore_hint: String,
+    pub restore_button: String,
+    pub restore_expired: String,
+    /// `{user}` mentions the author of the original
+    pub restored: String,
+    pub no_delete_in_dm: String,
+    /// replies to bright images behind links, which are converted without asking
+    pub linked_image_done: String,
+    pub linked_gif_done: String,
+    /// reply to the 🌙 reaction
+    pub moon_done: String,
+    /// above the list of attachments which were not converted
+    pub skipped: String,
+    pub downloading: String,
+    pub converting: String,
+    /// `{done}` and `{total}` are the numbers of frames
+    pub converting_frames: String,
+    /// replies to /nord, `{error}` is what went wrong
+    pub not_convertible: String,
+    pub no_image_given: String,
+    pub download_failed: String,
+    pub conversion_failed: String,
+    /// `{name}` is the palette asked for, `{palettes}` are the known ones
+    pub unknown_palette: String,
+    pub no_custom_palette: String,
+    pub groups_need_builtin_palette: String,
+    pub too_few_group_colors: String,
+    /// `{name}` is the group asked for, `{groups}` are the known ones
+    pub unknown_color_group: String,
+    pub no_color_group: String,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Messages {
+            ask_to_darken: String::from(
+                "Bruhh... This looks bright as fuck. On a scale **from 1 to 9 it's a {scale}**.\nMay I darken it?"
+            ),
+            ask_to_darken_spoiler: String::from("This spoiler looks bright. May I darken it?"),
+            analysis: String::from("Main colors: {colors} · average brightness {brightness}"),
+            ask_to_lighten: String::from("Dark as night. May I brighten it up with {palette}?"),
+            no_button: String::from("No"),
+            dithered_button: String::from("Darken (dithered)"),
+            tint_button: String::from("Tint"),
+            gradient_button: String::from("Gradient"),
+            bright_only_button: String::from("Darken bright parts only"),
+            prompt_expired: String::from("This prompt expired, re-upload to try again."),
+            outdated_button: String::from("This button is from an older version of me, re-upload to try again."),
+            working: String::from("⌛ I'm working on it. Please wait a moment."),
+            changing_options: String::from("⌛ I change the options. Please wait a moment."),
+            options_changed: String::from("Edited your options."),
+            image_vanished: String::from("Seems like the bright picture has vanished. I can't darken what I can't see."),
+            rate_limited: String::from("Slow down! You darkened a lot of images in the last minute. Try again in a bit."),
+            in_line: String::from("⌛ You're #{position} in line. I'll start as soon as I can."),
+            cancel_button: String::from("Cancel"),
+            cancelled: String::from("Cancelled"),
+            already_started: String::from("Too late, I'm already working on it."),
+            done: String::from("Here it is! May I delete your shiny one?"),
+            palette_used: String::from("Palette: **{palette}**"),
+            disposed: String::from("I have thrown it deep into the void. Enjoy the darkness!"),
+            restore_hint: String::from("You can still restore it for {minutes} minutes."),
+            restore_button: String::from("Restore original"),
+            restore_expired: String::from("Too late, the original is gone for good."),
+            restored: String::from("Restored the original of {user}:"),
+            no_delete_in_dm: String::from("I'm not allowed to delete your messages in DMs, but you can do it yourself."),
+            linked_image_done: String::from("That image was way too bright. Here is a darker one:"),
+            linked_gif_done: String::from("That GIF was way too bright. Here is a darker one:"),
+            moon_done: String::from("🌙 Here is the dark version."),
+            skipped: String::from("I had to skip these:"),
+            downloading: String::from("⌛ Downloading"),
+            converting: String::from("⌛ Converting"),
+            converting_frames: String::from("⌛ Frame {done}/{total}"),
+            not_convertible: String::from("I can't convert that: {error}"),
+            no_image_given: String::from("Give me an image attachment or a link to an image and I'll darken it."),
+            download_failed: String::from("Sorry, I couldn't grab that image: {error}"),
+            conversion_failed: String::from("Sorry, I couldn't convert that image: {error}"),
+            unknown_palette: String::from("I don't know the palette `{name}`. Pick one of: {palettes}"),
+            no_custom_palette: String::from("There is no custom palette yet, set one with /set_custom_palette."),
+            groups_need_builtin_palette: String::from("Color groups only work with the built in palettes."),
+            too_few_group_colors: String::from("Those groups leave fewer than two colors, pick some more."),
+            unknown_color_group: String::from("I don't know the color group `{name}`. Pick from: {groups}"),
+            no_color_group: String::from("Name at least one color group: {groups}"),
+        }
+    }
+}
+
+impl Messages {
+    /// missing keys keep their default text
+    pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+}
+
+/// replaces `{key}` with the value
+pub fn fill(template: &str, key: &str, value: impl ToString) -> String {
+    template.replace(&format!("{{{key}}}"), &value.to_string())
+}
+
+/// LOCALES_DIR, `locales` next to the binary by default
+pub fn locales_dir() -> PathBuf {
+    PathBuf::from(env::var("LOCALES_DIR").unwrap_or_else(|_| String::from("locales")))
+}
+
+/// reads every `<locale>.toml` of the directory, the default english texts are always available
+pub fn load_locales(directory: &Path) -> HashMap<String, Messages> {
+    let mut locales = HashMap::from([(DEFAULT_LOCALE.to_string(), Messages::default())]);
+    let Ok(entries) = fs::read_dir(directory) else {
+        return locales;
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+        if path.extension().and_then(|extension| extension.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        match fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|content| Messages::from_toml(&content)) {
+            Ok(messages) => {
+                locales.insert(locale.to_string(), messages);
+            }
+            Err(e) => warn!("Skipping locale {}: {:?}", path.display(), e),
+        }
+    }
+    locales
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_overrides_keep_the_defaults() {
+        let messages = Messages::from_toml("no_button = \"Nope\"\ndone = \"Done, {palette}\"").unwrap();
+        let defaults = Messages::default();
+        assert_eq!(messages.no_button, "Nope");
+        assert_eq!(messages.done, "Done, {palette}");
+        assert_eq!(messages.ask_to_darken, defaults.ask_to_darken);
+        assert_eq!(messages.restore_button, defaults.restore_button);
+    }
+
+    #[test]
+    fn bundled_locales_load_next_to_english() {
+        let locales = load_locales(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/locales")));
+        assert!(locales.contains_key(DEFAULT_LOCALE));
+        let polite = &locales["en-polite"];
+        assert_ne!(polite.ask_to_darken, Messages::default().ask_to_darken);
+        assert_eq!(polite.no_button, Messages::default().no_button);
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(fill("You're #{position} in line.", "position", 3), "You're #3 in line.");
+    }
+}
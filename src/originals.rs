@@ -0,0 +1,76 @@
+use std::{sync::Mutex, time::Duration};
+
+use serenity::all::UserId;
+use ttl_cache::TtlCache;
+
+
+/// how long a disposed original can be restored
+pub const RESTORE_WINDOW: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct Original {
+    pub author: UserId,
+    /// filename and bytes of every attachment
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// originals of disposed messages, keyed by the id of the deleted message
+pub struct OriginalStore {
+    originals: Mutex<TtlCache<u64, Original>>,
+    window: Duration,
+}
+
+impl OriginalStore {
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        OriginalStore {
+            originals: Mutex::new(TtlCache::new(capacity)),
+            window,
+        }
+    }
+
+    pub fn insert(&self, message_id: u64, original: Original) {
+        self.originals.lock().unwrap().insert(message_id, original, self.window);
+    }
+
+    /// hands out the original once, None if the window has passed
+    pub fn take(&self, message_id: u64) -> Option<Original> {
+        self.originals.lock().unwrap().remove(&message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original(filename: &str) -> Original {
+        Original { author: UserId::new(1), files: vec![(filename.to_string(), vec![1, 2, 3])] }
+    }
+
+    #[test]
+    fn originals_can_be_restored_once() {
+        let store = OriginalStore::new(4, RESTORE_WINDOW);
+        store.insert(10, original("cat.png"));
+        let restored = store.take(10).unwrap();
+        assert_eq!(restored.author, UserId::new(1));
+        assert_eq!(restored.files, vec![("cat.png".to_string(), vec![1, 2, 3])]);
+        assert!(store.take(10).is_none());
+    }
+
+    #[test]
+    fn originals_expire_after_the_window() {
+        let store = OriginalStore::new(4, Duration::from_millis(20));
+        store.insert(10, original("cat.png"));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(store.take(10).is_none());
+    }
+
+    #[test]
+    fn full_store_drops_the_oldest_original() {
+        let store = OriginalStore::new(2, RESTORE_WINDOW);
+        for id in 1..=3 {
+            store.insert(id, original("cat.png"));
+        }
+        assert!(store.take(1).is_none());
+        assert!(store.take(2).is_some() && store.take(3).is_some());
+    }
+}
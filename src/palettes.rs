@@ -0,0 +1,147 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use log::warn;
+
+use crate::{colors::Palette, config::parse_hex_color};
+
+
+/// the built in palettes and the named ones from palettes.toml, so adding one doesn't need a new build
+pub struct PaletteRegistry {
+    palettes: BTreeMap<String, Vec<[u8; 3]>>,
+}
+
+impl PaletteRegistry {
+    /// the built in palettes and the ones on disk, which can't replace a built in one
+    pub fn load() -> Self {
+        let mut registry = PaletteRegistry::builtin();
+        let path = palettes_path();
+        if let Ok(content) = fs::read_to_string(&path) {
+            registry.add(PaletteRegistry::parse(&content));
+        }
+        registry
+    }
+
+    /// every palette of the Palette enum under its id, the colors only exist in colors.rs
+    pub fn builtin() -> Self {
+        let palettes = Palette::iter()
+            .into_iter()
+            .filter(|palette| *palette != Palette::Custom)
+            .map(|palette| {
+                let colors = palette.colors().iter().map(|color| {
+                    let [r, g, b, _] = color.to_rgba().0;
+                    [r, g, b]
+                }).collect();
+                (palette.as_str().to_string(), colors)
+            })
+            .collect();
+        PaletteRegistry { palettes }
+    }
+
+    /// built in palettes know their color groups, so a palette of the same name is left out
+    fn add(&mut self, other: PaletteRegistry) {
+        for (name, colors) in other.palettes {
+            if name.parse::<Palette>().is_ok() {
+                warn!("Skipping palette name={} reason=a built in palette has this name", name);
+                continue;
+            }
+            self.palettes.insert(name, colors);
+        }
+    }
+
+    /// malformed palettes are logged and left out
+    pub fn parse(content: &str) -> Self {
+        let mut palettes = BTreeMap::new();
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("Skipping palette file reason={}", e);
+                return PaletteRegistry { palettes };
+            }
+        };
+        for (name, value) in table {
+            match parse_palette(&value) {
+                Ok(colors) => {
+                    palettes.insert(name.to_lowercase(), colors);
+                }
+                Err(e) => warn!("Skipping palette name={} reason={}", name, e),
+            }
+        }
+        PaletteRegistry { palettes }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[[u8; 3]]> {
+        self.palettes.get(&name.to_lowercase()).map(Vec::as_slice)
+    }
+
+    /// sorted by name
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.palettes.keys().map(String::as_str)
+    }
+}
+
+fn parse_palette(value: &toml::Value) -> anyhow::Result<Vec<[u8; 3]>> {
+    let Some(codes) = value.as_array() else {
+        anyhow::bail!("expected a list of hex colors");
+    };
+    if codes.is_empty() {
+        anyhow::bail!("a palette needs at least one color");
+    }
+    codes
+        .iter()
+        .map(|code| match code.as_str() {
+            Some(code) => parse_hex_color(code),
+            None => anyhow::bail!("`{code}` is not a hex color"),
+        })
+        .collect()
+}
+
+/// PALETTES_PATH, `palettes.toml` next to the binary by default
+fn palettes_path() -> PathBuf {
+    PathBuf::from(env::var("PALETTES_PATH").unwrap_or_else(|_| String::from("palettes.toml")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_palette_file() {
+        let registry = PaletteRegistry::parse(r##"
+            Dusk = ["#1a1b26", "#7aa2f7", "#f7768e"]
+            short = ["#fff", "#000"]
+        "##);
+        assert_eq!(registry.names().collect::<Vec<_>>(), vec!["dusk", "short"]);
+        let dusk = registry.get("dusk").unwrap();
+        assert_eq!(dusk.len(), 3);
+        assert_eq!(dusk[0], [0x1a, 0x1b, 0x26]);
+        assert_eq!(dusk[1], [0x7a, 0xa2, 0xf7]);
+        assert_eq!(dusk[2], [0xf7, 0x76, 0x8e]);
+        assert_eq!(registry.get("short").unwrap(), &[[255, 255, 255], [0, 0, 0]]);
+    }
+
+    #[test]
+    fn skips_malformed_palettes() {
+        let registry = PaletteRegistry::parse(r##"
+            good = ["#88c0d0"]
+            empty = []
+            typo = ["#88c0dz"]
+            number = 5
+        "##);
+        assert_eq!(registry.names().collect::<Vec<_>>(), vec!["good"]);
+        assert!(PaletteRegistry::parse("not = [toml").names().next().is_none());
+    }
+
+    #[test]
+    fn built_in_palettes_come_from_colors() {
+        let mut registry = PaletteRegistry::builtin();
+        assert_eq!(registry.get("nord").unwrap().len(), Palette::Nord.colors().len());
+        assert_eq!(registry.get("nord").unwrap()[0], [0x2e, 0x34, 0x40]);
+        assert!(registry.get("custom").is_none());
+        registry.add(PaletteRegistry::parse(r##"
+            nord = ["#000000"]
+            dusk = ["#1a1b26"]
+        "##));
+        assert_eq!(registry.get("nord").unwrap().len(), Palette::Nord.colors().len());
+        assert!(registry.get("dusk").is_some());
+    }
+}
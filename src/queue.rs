@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex},
+};
+
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+
+
+/// limits how many images get converted at the same time
+pub struct ConversionQueue {
+    workers: Semaphore,
+    waiting: AtomicUsize,
+    /// jobs which can still be cancelled, by token
+    pending: Mutex<HashMap<u64, Arc<Notify>>>,
+    next_token: AtomicU64,
+}
+
+/// decrements the waiting count, even if the waiting future gets dropped
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ConversionQueue {
+    pub fn new(workers: usize) -> Self {
+        ConversionQueue {
+            workers: Semaphore::new(workers.max(1)),
+            waiting: AtomicUsize::new(0),
+            pending: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+        }
+    }
+
+    /// place in line a new request would get, 0 if a worker is free
+    pub fn position(&self) -> usize {
+        if self.workers.available_permits() > 0 {
+            0
+        } else {
+            self.waiting.load(Ordering::SeqCst) + 1
+        }
+    }
+
+    /// waits for a free worker, which is released again when the permit is dropped
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let _waiting = Waiting(&self.waiting);
+        self.workers.acquire().await.expect("the conversion queue is never closed")
+    }
+
+    /// registers a job which can be cancelled until it starts, the token goes into the cancel button
+    pub fn enqueue(&self) -> u64 {
+        let token = self.next_token.fetch_add(1, Ordering::SeqCst);
+        self.pending.lock().unwrap().insert(token, Arc::new(Notify::new()));
+        token
+    }
+
+    /// like `acquire`, but None if the job was cancelled before a worker was free
+    pub async fn acquire_pending(&self, token: u64) -> Option<SemaphorePermit<'_>> {
+        let cancelled = self.pending.lock().unwrap().get(&token).cloned()?;
+        let permit = tokio::select! {
+            permit = self.acquire() => permit,
+            _ = cancelled.notified() => return None,
+        };
+        // cancelling and starting can race, whoever removes the token wins
+        self.pending.lock().unwrap().remove(&token)?;
+        Some(permit)
+    }
+
+    /// cancels a job which hasn't started yet, false if it already runs or is gone
+    pub fn cancel(&self, token: u64) -> bool {
+        match self.pending.lock().unwrap().remove(&token) {
+            Some(cancelled) => {
+                // stores a permit, so a job which isn't waiting yet still sees it
+                cancelled.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn second_job_waits_for_the_first() {
+        let queue = ConversionQueue::new(1);
+        let first = queue.acquire().await;
+        assert_eq!(queue.position(), 1);
+        assert!(tokio::time::timeout(Duration::from_millis(50), queue.acquire()).await.is_err());
+
+        drop(first);
+        assert_eq!(queue.position(), 0);
+        assert!(tokio::time::timeout(Duration::from_millis(50), queue.acquire()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn jobs_can_be_cancelled_until_they_start() {
+        let queue = ConversionQueue::new(1);
+        let cancelled = queue.enqueue();
+        assert!(queue.cancel(cancelled));
+        assert!(queue.acquire_pending(cancelled).await.is_none());
+        assert!(!queue.cancel(cancelled));
+
+        let started = queue.enqueue();
+        let permit = queue.acquire_pending(started).await;
+        assert!(permit.is_some());
+        assert!(!queue.cancel(started));
+    }
+
+    #[tokio::test]
+    async fn waiting_jobs_stop_when_cancelled() {
+        let queue = Arc::new(ConversionQueue::new(1));
+        let busy = queue.acquire().await;
+        let token = queue.enqueue();
+        let waiting = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.acquire_pending(token).await.is_some() })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(queue.cancel(token));
+        assert!(!waiting.await.unwrap());
+        drop(busy);
+    }
+}
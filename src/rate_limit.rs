@@ -0,0 +1,72 @@
+use std::{
+    collections::HashMap, env, sync::Mutex, time::{Duration, Instant}
+};
+
+use serenity::all::UserId;
+
+
+const WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_CONVERSIONS_PER_MINUTE: usize = 5;
+
+/// sliding window limit of conversions per user
+pub struct RateLimiter {
+    max_per_window: usize,
+    conversions: Mutex<HashMap<UserId, Vec<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_per_window: usize) -> Self {
+        RateLimiter {
+            max_per_window,
+            conversions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// reads the limit from CONVERSIONS_PER_MINUTE
+    pub fn from_env() -> Self {
+        let max_per_window = env::var("CONVERSIONS_PER_MINUTE")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_CONVERSIONS_PER_MINUTE);
+        RateLimiter::new(max_per_window)
+    }
+
+    /// counts a conversion for the user, unless the user already used up the limit
+    pub fn is_rate_limited(&self, user: UserId, now: Instant) -> bool {
+        let mut conversions = self.conversions.lock().unwrap();
+        let timestamps = conversions.entry(user).or_default();
+        timestamps.retain(|time| now.duration_since(*time) < WINDOW);
+        if timestamps.len() >= self.max_per_window {
+            return true;
+        }
+        timestamps.push(now);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_conversions_within_the_window() {
+        let limiter = RateLimiter::new(2);
+        let (user, other) = (UserId::new(1), UserId::new(2));
+        let start = Instant::now();
+        assert!(!limiter.is_rate_limited(user, start));
+        assert!(!limiter.is_rate_limited(user, start + Duration::from_secs(10)));
+        assert!(limiter.is_rate_limited(user, start + Duration::from_secs(20)));
+        // every user has their own window
+        assert!(!limiter.is_rate_limited(other, start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn old_conversions_slide_out_of_the_window() {
+        let limiter = RateLimiter::new(1);
+        let user = UserId::new(1);
+        let start = Instant::now();
+        assert!(!limiter.is_rate_limited(user, start));
+        assert!(limiter.is_rate_limited(user, start + WINDOW - Duration::from_secs(1)));
+        assert!(!limiter.is_rate_limited(user, start + WINDOW));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::HashMap, sync::Mutex};
+
+
+/// counts successful conversions per palette, keyed by the id of the palette which was applied,
+/// like `nord`, the name of a palette from palettes.toml or `nord:frost+aurora` for color groups
+pub struct ConversionCounter {
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl ConversionCounter {
+    pub fn new() -> Self {
+        ConversionCounter {
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, palette: &str) {
+        *self.counts.lock().unwrap().entry(palette.to_string()).or_default() += 1;
+    }
+
+    /// passes the result of a conversion through and counts it, only if it succeeded
+    pub fn record_result<T, E>(&self, palette: &str, result: Result<T, E>) -> Result<T, E> {
+        if result.is_ok() {
+            self.record(palette);
+        }
+        result
+    }
+
+    /// total and the count of every used palette, most used first
+    pub fn totals(&self) -> (u64, Vec<(String, u64)>) {
+        let counts = self.counts.lock().unwrap();
+        let mut per_palette: Vec<(String, u64)> = counts.iter().map(|(palette, count)| (palette.clone(), *count)).collect();
+        per_palette.sort_by(|a, b| b.1.cmp(&a.1));
+        (per_palette.iter().map(|(_, count)| count).sum(), per_palette)
+    }
+}
+
+/// 1204 -> "1,204"
+pub fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_successful_conversions_once() {
+        let counter = ConversionCounter::new();
+        let converted: Result<(), &str> = counter.record_result("nord", Ok(()));
+        assert!(converted.is_ok());
+        assert_eq!(counter.totals(), (1, vec![(String::from("nord"), 1)]));
+    }
+
+    #[test]
+    fn ignores_failed_conversions() {
+        let counter = ConversionCounter::new();
+        let failed: Result<(), &str> = counter.record_result("nord", Err("download failed"));
+        assert!(failed.is_err());
+        assert_eq!(counter.totals(), (0, Vec::new()));
+    }
+
+    #[test]
+    fn formats_thousands() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1204), "1,204");
+        assert_eq!(format_count(1234567), "1,234,567");
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use resvg::{tiny_skia, usvg};
+
+
+/// SVGs smaller than this get rendered bigger, so thin lines survive the conversion
+const MIN_DIMENSION: f32 = 512.0;
+/// the default max_dimension of config.toml, the configured limit is applied afterwards
+const MAX_DIMENSION: f32 = 2048.0;
+
+/// checks for `<svg`, possibly after an xml declaration, comments or a doctype
+pub fn is_svg(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(1024)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with('<') && head.contains("<svg")
+}
+
+/// renders the SVG with its longer side between MIN_DIMENSION and MAX_DIMENSION,
+/// SVGs without width, height and viewBox are 100x100 for usvg
+pub fn rasterize(bytes: &[u8]) -> Result<DynamicImage> {
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default())
+        .context("That SVG is broken, I can't draw it")?;
+    let size = tree.size();
+    let longer_side = size.width().max(size.height());
+    let scale = longer_side.clamp(MIN_DIMENSION, MAX_DIMENSION) / longer_side;
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).context("That SVG has no size")?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    // tiny-skia works with premultiplied alpha
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, color) in image.pixels_mut().zip(pixmap.pixels()) {
+        let color = color.demultiply();
+        *pixel = Rgba([color.red(), color.green(), color.blue(), color.alpha()]);
+    }
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &[u8] = br##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50" viewBox="0 0 100 50">
+  <rect x="25" y="0" width="50" height="50" fill="#bf616a"/>
+</svg>"##;
+
+    #[test]
+    fn small_svgs_are_rendered_bigger() {
+        assert!(is_svg(SMALL));
+        let image = rasterize(SMALL).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (512, 256));
+        assert_eq!(*image.get_pixel(256, 128), Rgba([0xbf, 0x61, 0x6a, 255]));
+        // outside the rect the background stays transparent
+        assert_eq!(image.get_pixel(10, 128)[3], 0);
+    }
+
+    #[test]
+    fn huge_svgs_are_clamped() {
+        let huge = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10000" height="5000"><rect width="10000" height="5000" fill="white"/></svg>"#;
+        let image = rasterize(huge).unwrap();
+        assert_eq!((image.width(), image.height()), (2048, 1024));
+    }
+
+    #[test]
+    fn other_files_are_no_svgs() {
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_svg(b"<html><body>no image</body></html>"));
+    }
+}
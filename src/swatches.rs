@@ -0,0 +1,121 @@
+use ab_glyph::{FontRef, PxScale};
+use image::{DynamicImage, Rgba, RgbaImage};
+use imageproc::{drawing::{draw_filled_rect_mut, draw_text_mut}, rect::Rect};
+
+use crate::colors::{ConversionStats, Palette, RgbColor};
+
+
+const SWATCH_WIDTH: u32 = 180;
+const SWATCH_HEIGHT: u32 = 120;
+const COLUMNS: u32 = 4;
+
+/// draws one labeled rectangle per palette color
+pub fn render_swatches(palette: Palette) -> DynamicImage {
+    render_color_swatches(&palette.colors())
+}
+
+pub fn render_color_swatches(colors: &[RgbColor]) -> DynamicImage {
+    render_swatch_grid(colors, COLUMNS)
+}
+
+/// all colors next to each other in a single row
+pub fn render_color_strip(colors: &[RgbColor]) -> DynamicImage {
+    render_swatch_grid(colors, colors.len().max(1) as u32)
+}
+
+fn render_swatch_grid(colors: &[RgbColor], columns: u32) -> DynamicImage {
+    let rows = (colors.len() as u32 + columns - 1) / columns;
+    let mut image = RgbaImage::new(SWATCH_WIDTH * columns, SWATCH_HEIGHT * rows);
+
+    let font = FontRef::try_from_slice(include_bytes!("../assets/font.ttf")).unwrap();
+    let scale = PxScale { x: 30., y: 30. };
+
+    for (i, color) in colors.iter().enumerate() {
+        let x = (i as u32 % columns) * SWATCH_WIDTH;
+        let y = (i as u32 / columns) * SWATCH_HEIGHT;
+        draw_filled_rect_mut(
+            &mut image,
+            Rect::at(x as i32, y as i32).of_size(SWATCH_WIDTH, SWATCH_HEIGHT),
+            color.to_rgba()
+        );
+        // dark text on light colors and the other way around
+        let text_color = if color.brightness() > 0.5 {
+            Rgba([0u8, 0u8, 0u8, 255u8])
+        } else {
+            Rgba([255u8, 255u8, 255u8, 255u8])
+        };
+        draw_text_mut(
+            &mut image, text_color, 
+            x as i32 + 12, (y + SWATCH_HEIGHT) as i32 - 40, 
+            scale, &font, &color.to_hex()
+        );
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+const BAR_WIDTH: u32 = 48;
+const CHART_HEIGHT: u32 = 240;
+// nord0, so light and dark bars both stand out
+const CHART_BACKGROUND: Rgba<u8> = Rgba([46, 52, 64, 255]);
+
+/// one bar per palette color, a bar of full height means every visible pixel got that color
+pub fn render_usage_chart(stats: &ConversionStats, palette: &[RgbColor]) -> DynamicImage {
+    let usage = stats.palette_usage(palette);
+    let width = BAR_WIDTH * (palette.len() as u32).max(1);
+    let mut image = RgbaImage::from_pixel(width, CHART_HEIGHT, CHART_BACKGROUND);
+    for (i, (color, share)) in palette.iter().zip(usage).enumerate() {
+        let height = (share * CHART_HEIGHT as f32).round() as u32;
+        if height == 0 {
+            continue;
+        }
+        draw_filled_rect_mut(
+            &mut image,
+            Rect::at((i as u32 * BAR_WIDTH) as i32, (CHART_HEIGHT - height) as i32).of_size(BAR_WIDTH, height),
+            color.to_rgba()
+        );
+    }
+    DynamicImage::ImageRgba8(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_swatch_per_palette_color() {
+        let colors = Palette::Nord.colors();
+        let image = render_swatches(Palette::Nord).to_rgba8();
+        let rows = (colors.len() as u32 + COLUMNS - 1) / COLUMNS;
+        assert_eq!(image.dimensions(), (SWATCH_WIDTH * COLUMNS, SWATCH_HEIGHT * rows));
+        for (i, color) in colors.iter().enumerate() {
+            // the label sits in the bottom left, the top left corner keeps the plain color
+            let x = (i as u32 % COLUMNS) * SWATCH_WIDTH + 2;
+            let y = (i as u32 / COLUMNS) * SWATCH_HEIGHT + 2;
+            assert_eq!(*image.get_pixel(x, y), color.to_rgba(), "swatch {}", color.to_hex());
+        }
+    }
+
+    #[test]
+    fn white_image_fills_one_bar() {
+        let white = RgbaImage::from_pixel(16, 16, Rgba([255, 255, 255, 255]));
+        let stats = ConversionStats::from_image(&white);
+        let palette = Palette::Nord.colors();
+        let chart = render_usage_chart(&stats, &palette).to_rgba8();
+        assert_eq!(chart.dimensions(), (BAR_WIDTH * palette.len() as u32, CHART_HEIGHT));
+
+        let full_bars: Vec<usize> = (0..palette.len())
+            .filter(|i| {
+                let x = *i as u32 * BAR_WIDTH + BAR_WIDTH / 2;
+                (0..CHART_HEIGHT).all(|y| *chart.get_pixel(x, y) != CHART_BACKGROUND)
+            })
+            .collect();
+        assert_eq!(full_bars.len(), 1);
+        let lightest = palette.iter().max_by(|c1, c2| c1.brightness().total_cmp(&c2.brightness())).unwrap();
+        assert_eq!(&palette[full_bars[0]], lightest);
+        // every other bar is empty
+        for i in (0..palette.len()).filter(|i| *i != full_bars[0]) {
+            let x = i as u32 * BAR_WIDTH + BAR_WIDTH / 2;
+            assert_eq!(*chart.get_pixel(x, CHART_HEIGHT - 1), CHART_BACKGROUND);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::{path::PathBuf, process::Command};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn output_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("image2nord-{}-{name}", std::process::id()))
+}
+
+#[test]
+fn converts_a_local_png() {
+    let input = fixture("gradient.png");
+    let output = output_path("gradient.png");
+    let result = Command::new(env!("CARGO_BIN_EXE_image2nord"))
+        .args(["convert", input.to_str().unwrap(), output.to_str().unwrap(), "--palette", "nord", "--strength", "1.0"])
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let original = image::open(&input).unwrap().to_rgba8();
+    let converted = image::open(&output).unwrap().to_rgba8();
+    std::fs::remove_file(&output).ok();
+    assert_eq!(converted.dimensions(), original.dimensions());
+    assert_ne!(converted, original);
+}
+
+#[test]
+fn failures_exit_with_an_error() {
+    let output = output_path("missing.png");
+    let result = Command::new(env!("CARGO_BIN_EXE_image2nord"))
+        .args(["convert", fixture("missing.png").to_str().unwrap(), output.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Failed to read"));
+    assert!(!output.exists());
+}